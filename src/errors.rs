@@ -1,45 +1,813 @@
-use colored::Colorize;
-use pest::error::LineColLocation;
+use std::{cell::RefCell, collections::BTreeMap, fmt, fs, path::Path, rc::Rc};
 
-use crate::Rule;
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use pest::error::{Error, InputLocation, LineColLocation};
+use serde_json::{json, Value};
 
-pub fn format_error(error: pest::error::Error<Rule>) -> String {
-    let code = error.line();
-    let pos = match error.line_col {
-        LineColLocation::Pos(x) => x,
-        LineColLocation::Span(x, _) => x,
+use crate::{parser::Span, Rule};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// A bug in walter, found at the code it was compiling
+    InternalError,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::InternalError => "internal error",
+        }
+    }
+
+    /// The `level` rustc gives its JSON diagnostics
+    fn rustc_level(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::InternalError => "error: internal compiler error",
+        }
+    }
+}
+
+/// How diagnostics are printed
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MessageFormat {
+    /// Colored text with the code around each error
+    #[default]
+    Human,
+    /// One JSON object per line on stdout, in the format of rustc's `--error-format json`
+    Json,
+}
+
+impl MessageFormat {
+    /// Prints an error that isn't about a place in the code, like a failed link. In JSON it is a
+    /// message without spans, so tools see it too.
+    pub fn error(self, message: impl fmt::Display) {
+        match self {
+            MessageFormat::Human => log::error!("{}", message),
+            MessageFormat::Json => {
+                let message = message.to_string();
+                let rendered = format!("error: {}\n", message);
+                println!("{}", json_message("error", &message, Some(rendered)));
+            }
+        }
+    }
+}
+
+/// What went wrong, independent of the wording of the message. Codes never change meaning, so
+/// they can be searched for and matched by tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    /// The parse tree doesn't match the grammar
+    Internal,
+    /// The source doesn't match the grammar
+    Syntax,
+    InvalidModifier,
+    DuplicateArgument,
+    InvalidNumber,
+    NotAnExpression,
+    /// A constructor or destructor with the wrong name or arguments
+    InvalidSpecialMethod,
+    BreakOutsideLoop,
+    /// `weneed`, `bringme` or `subreddit` where they can't be used
+    MisplacedModule,
+    /// An import that can't be loaded
+    Import,
+    /// The standard library is used in a nostd build
+    Nostd,
+    UndefinedVariable,
+    /// A function, method or class that doesn't exist
+    Undefined,
+    ArgumentCount,
+    DuplicateDefinition,
+    TypeMismatch,
+    InvalidMath,
+    IndexOutOfBounds,
+    InvalidClassMember,
+    /// A field that can't be read from where it is read
+    InaccessibleField,
+    NoReturnValue,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::Internal => "RL0000",
+            Code::Syntax => "RL0001",
+            Code::InvalidModifier => "RL0002",
+            Code::DuplicateArgument => "RL0003",
+            Code::InvalidNumber => "RL0004",
+            Code::NotAnExpression => "RL0005",
+            Code::InvalidSpecialMethod => "RL0006",
+            Code::BreakOutsideLoop => "RL0007",
+            Code::MisplacedModule => "RL0008",
+            Code::Import => "RL0009",
+            Code::Nostd => "RL0010",
+            Code::UndefinedVariable => "RL0011",
+            Code::Undefined => "RL0012",
+            Code::ArgumentCount => "RL0013",
+            Code::DuplicateDefinition => "RL0014",
+            Code::TypeMismatch => "RL0015",
+            Code::InvalidMath => "RL0016",
+            Code::IndexOutOfBounds => "RL0017",
+            Code::InvalidClassMember => "RL0018",
+            Code::InaccessibleField => "RL0019",
+            Code::NoReturnValue => "RL0020",
+        }
+    }
+}
+
+/// Code related to a diagnostic, with what it has to do with it
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An error at a node of the AST
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    /// The code that is wrong, underlined with `^`
+    pub span: Span,
+    /// Printed next to the underline of `span`
+    pub label: Option<String>,
+    /// Other code that explains the error, underlined with `-`
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: Code, span: &Span, message: impl ToString) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            span: span.clone(),
+            label: None,
+            secondary: vec![],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    /// A bug in walter that showed up while compiling `span`
+    pub fn internal(span: &Span, information: impl ToString) -> Self {
+        Self {
+            severity: Severity::InternalError,
+            ..Self::new(Code::Internal, span, ERR_BUG)
+        }
+        .with_note(format!(
+            "Additional Information: {}",
+            information.to_string()
+        ))
+    }
+
+    /// A syntax error found by pest in `file`
+    pub fn from_pest(error: Error<Rule>, file: &Path) -> Self {
+        let (start, end) = match error.location {
+            InputLocation::Pos(x) => (x, x),
+            InputLocation::Span(x) => x,
+        };
+        let (line, col) = match error.line_col {
+            LineColLocation::Pos(x) => x,
+            LineColLocation::Span(x, _) => x,
+        };
+        let span = Span {
+            file: Rc::from(file),
+            start,
+            end,
+            line,
+            col,
+        };
+
+        Self::new(Code::Syntax, &span, error.variant.message())
+    }
+
+    pub fn with_label(mut self, message: impl ToString) -> Self {
+        self.label = Some(message.to_string());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: &Span, message: impl ToString) -> Self {
+        self.secondary.push(Label {
+            span: span.clone(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, message: impl ToString) -> Self {
+        self.notes.push(message.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, message: impl ToString) -> Self {
+        self.help.push(message.to_string());
+        self
+    }
+
+    /// The diagnostic as printed to a terminal. The source is read again, so the code around the
+    /// spans can be shown.
+    pub fn format(&self) -> String {
+        let header = format!("{}[{}]", self.severity.as_str(), self.code.as_str())
+            .red()
+            .bold();
+        let mut out = format!("{}{} {}\n", header, ":".bold(), self.message.bold());
+
+        // Labels are shown per file, the file of the primary span first
+        let mut files: Vec<(Rc<Path>, Vec<Marker>)> = vec![];
+        let labels = std::iter::once((&self.span, self.label.as_deref(), true)).chain(
+            self.secondary
+                .iter()
+                .map(|x| (&x.span, Some(x.message.as_str()), false)),
+        );
+        for (span, message, primary) in labels {
+            let index = match files.iter().position(|(x, _)| *x == span.file) {
+                Some(x) => x,
+                None => {
+                    files.push((span.file.clone(), vec![]));
+                    files.len() - 1
+                }
+            };
+            files[index].1.push(Marker {
+                span,
+                message,
+                primary,
+            });
+        }
+
+        let snippets: Vec<Snippet> = files
+            .iter()
+            .map(|(file, markers)| Snippet::new(file, markers))
+            .collect();
+        let padding = snippets
+            .iter()
+            .flat_map(|x| x.lines.keys().last())
+            .max()
+            .map_or(1, |x| x.to_string().len());
+        let bar = "|".blue().bold();
+
+        for (i, snippet) in snippets.iter().enumerate() {
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            if i > 0 {
+                out += &format!("{} {}\n", " ".repeat(padding), bar);
+            }
+            out += &snippet.render(arrow, padding);
+        }
+
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            out += &format!("{} {}\n", " ".repeat(padding), bar);
+        }
+        let notes = self.notes.iter().map(|x| ("note:", x));
+        let help = self.help.iter().map(|x| ("help:", x));
+        for (kind, message) in notes.chain(help) {
+            out += &format!(
+                "{} {} {} {}\n",
+                " ".repeat(padding),
+                "=".blue().bold(),
+                kind.bold(),
+                message
+            );
+        }
+        out
+    }
+}
+
+/// A span to underline
+struct Marker<'a> {
+    span: &'a Span,
+    message: Option<&'a str>,
+    primary: bool,
+}
+
+impl Marker<'_> {
+    fn paint(&self, text: &str) -> ColoredString {
+        match self.primary {
+            true => text.red().bold(),
+            false => text.blue().bold(),
+        }
+    }
+}
+
+/// A marker placed on the lines of its file. Lines count from 1 and columns from 0, the end is
+/// the column after the last character.
+struct Placed<'a> {
+    marker: &'a Marker<'a>,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+/// The lines of a file that markers point at
+struct Snippet<'a> {
+    /// Where the first marker is, as `file:line:col`
+    location: String,
+    /// Text of the shown lines, tabs replaced by spaces
+    lines: BTreeMap<usize, String>,
+    /// Markers on a single line, drawn under it
+    underlines: Vec<Placed<'a>>,
+    /// A marker across several lines, drawn in the margin. Other markers across several lines
+    /// are only underlined on their first line.
+    multiline: Option<Placed<'a>>,
+}
+
+/// Spaces a tab is shown as
+const TAB_WIDTH: usize = 4;
+
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|x| if x == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Line and column of a byte offset
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    (
+        before.matches('\n').count() + 1,
+        width(&before[line_start..]),
+    )
+}
+
+impl<'a> Snippet<'a> {
+    fn new(file: &'a Path, markers: &'a [Marker<'a>]) -> Self {
+        let source = fs::read_to_string(file).unwrap_or_default();
+        let source_lines: Vec<&str> = source.lines().collect();
+        let first = markers[0].span;
+        let mut snippet = Self {
+            location: format!("{}:{}:{}", file.display(), first.line, first.col),
+            lines: BTreeMap::new(),
+            underlines: vec![],
+            multiline: None,
+        };
+
+        for marker in markers {
+            let span = marker.span;
+            // The file changed since it was parsed
+            if span.end > source.len()
+                || !source.is_char_boundary(span.start)
+                || !source.is_char_boundary(span.end)
+            {
+                continue;
+            }
+
+            let start = position(&source, span.start);
+            let end = match source[span.start..span.end].chars().next_back() {
+                Some(x) => {
+                    let (line, col) = position(&source, span.end - x.len_utf8());
+                    (line, col + width(&x.to_string()))
+                }
+                // Empty spans point at the character after them
+                None => (start.0, start.1 + 1),
+            };
+            let mut placed = Placed { marker, start, end };
+
+            let mut shown = vec![start.0];
+            if start.0 != end.0 && snippet.multiline.is_none() {
+                // Long spans are shown by their first and last two lines
+                if end.0 - start.0 <= 4 {
+                    shown.extend(start.0 + 1..=end.0);
+                } else {
+                    shown.extend([start.0 + 1, end.0 - 1, end.0]);
+                }
+                snippet.multiline = Some(placed);
+            } else {
+                if start.0 != end.0 {
+                    let line = source_lines.get(start.0 - 1).copied().unwrap_or_default();
+                    placed.end = (start.0, width(line).max(start.1 + 1));
+                }
+                snippet.underlines.push(placed);
+            }
+
+            for line in shown {
+                let text = source_lines.get(line - 1).copied().unwrap_or_default();
+                snippet
+                    .lines
+                    .insert(line, text.replace('\t', &" ".repeat(TAB_WIDTH)));
+            }
+        }
+
+        snippet.underlines.sort_by_key(|x| x.start.1);
+        snippet
+    }
+
+    fn render(&self, arrow: &str, padding: usize) -> String {
+        let bar = "|".blue().bold();
+        let empty = " ".repeat(padding);
+        let mut out = format!("{}{} {}\n", empty, arrow.blue().bold(), self.location);
+        out += &format!("{} {}\n", empty, bar);
+
+        if self.lines.is_empty() {
+            return out;
+        }
+
+        // The multi-line marker starts a line when only indentation is before it
+        let starts_line = self.multiline.as_ref().is_some_and(|x| {
+            self.lines[&x.start.0]
+                .chars()
+                .take(x.start.1)
+                .all(char::is_whitespace)
+        });
+        // What is drawn in the margin next to and under a line
+        let margin = |line: usize, under: bool| -> ColoredString {
+            let x = match &self.multiline {
+                Some(x) => x,
+                None => return "".normal(),
+            };
+            let inside = match under {
+                true => line >= x.start.0 && line < x.end.0 && (line > x.start.0 || starts_line),
+                false => line > x.start.0 && line <= x.end.0,
+            };
+            match (inside, line == x.start.0 && starts_line && !under) {
+                (_, true) => x.marker.paint("/ "),
+                (true, false) => x.marker.paint("| "),
+                (false, false) => "  ".normal(),
+            }
+        };
+
+        let mut previous = None;
+        for (&line, text) in &self.lines {
+            if previous.is_some_and(|x| line > x + 1) {
+                out += &format!("{}\n", "...".blue().bold());
+            }
+            previous = Some(line);
+
+            let number = format!("{:>padding$}", line).blue().bold();
+            let source_line = format!("{} {} {}{}", number, bar, margin(line, false), text);
+            out += source_line.trim_end();
+            out.push('\n');
+
+            for underline in self.underlines.iter().filter(|x| x.start.0 == line) {
+                let marker = underline.marker;
+                let symbol = if marker.primary { "^" } else { "-" };
+                let length = underline.end.1.saturating_sub(underline.start.1).max(1);
+                let mut drawn = format!(
+                    "{}{}",
+                    " ".repeat(underline.start.1),
+                    marker.paint(&symbol.repeat(length))
+                );
+                if let Some(x) = marker.message {
+                    drawn += &format!(" {}", marker.paint(x));
+                }
+                out += &format!("{} {} {}{}\n", empty, bar, margin(line, true), drawn);
+            }
+
+            if let Some(x) = &self.multiline {
+                let symbol = if x.marker.primary { "^" } else { "-" };
+                if line == x.start.0 && !starts_line {
+                    let drawn = format!(" {}{}", "_".repeat(x.start.1 + 1), symbol);
+                    out += &format!("{} {} {}\n", empty, bar, x.marker.paint(&drawn));
+                }
+                if line == x.end.0 {
+                    let mut drawn = x
+                        .marker
+                        .paint(&format!("|{}{}", "_".repeat(x.end.1.max(1)), symbol))
+                        .to_string();
+                    if let Some(message) = x.marker.message {
+                        drawn += &format!(" {}", x.marker.paint(message));
+                    }
+                    out += &format!("{} {} {}\n", empty, bar, drawn);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Line and column of a byte offset the way rustc's JSON counts them, in characters from 1
+fn json_position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// A span as an entry of `spans` in rustc's JSON diagnostics
+fn json_span(span: &Span, label: Option<&str>, is_primary: bool) -> Value {
+    let source = fs::read_to_string(&span.file).unwrap_or_default();
+    let in_source = span.end <= source.len()
+        && source.is_char_boundary(span.start)
+        && source.is_char_boundary(span.end);
+    let (start, end) = match in_source {
+        true => (
+            json_position(&source, span.start),
+            json_position(&source, span.end),
+        ),
+        // The file changed since it was parsed
+        false => ((span.line, span.col), (span.line, span.col)),
+    };
+
+    let text: Vec<Value> = match in_source {
+        true => source
+            .lines()
+            .enumerate()
+            .skip(start.0 - 1)
+            .take(end.0 - start.0 + 1)
+            .map(|(i, line)| {
+                json!({
+                    "text": line,
+                    "highlight_start": if i + 1 == start.0 { start.1 } else { 1 },
+                    "highlight_end": if i + 1 == end.0 { end.1 } else { line.chars().count() + 1 },
+                })
+            })
+            .collect(),
+        false => vec![],
     };
-    let line_padding = " ".repeat(pos.0.to_string().len());
-    let error_arrow_padding = " ".repeat(pos.1);
 
-    let message = error.variant.message().to_string().red().bold();
+    json!({
+        "file_name": span.file.display().to_string(),
+        "byte_start": span.start,
+        "byte_end": span.end,
+        "line_start": start.0,
+        "line_end": end.0,
+        "column_start": start.1,
+        "column_end": end.1,
+        "is_primary": is_primary,
+        "text": text,
+        "label": label,
+        "suggested_replacement": null,
+        "suggestion_applicability": null,
+        "expansion": null,
+    })
+}
 
-    let colored_line = pos.0.to_string().blue().bold();
-    let colored_col = pos.1.to_string().blue().bold();
+/// A message without spans, such as a note or the summary at the end
+fn json_message(level: &str, message: &str, rendered: Option<String>) -> Value {
+    json!({
+        "$message_type": "diagnostic",
+        "message": message,
+        "code": null,
+        "level": level,
+        "spans": [],
+        "children": [],
+        "rendered": rendered,
+    })
+}
 
-    let colored_error_position = format!("{}{}{}", colored_line, ":".blue().bold(), colored_col);
+impl Diagnostic {
+    /// The diagnostic in the format of rustc's `--error-format json`
+    pub fn to_json(&self) -> Value {
+        let mut spans = vec![json_span(&self.span, self.label.as_deref(), true)];
+        spans.extend(
+            self.secondary
+                .iter()
+                .map(|x| json_span(&x.span, Some(&x.message), false)),
+        );
 
-    let colored_bar = "|".blue().bold();
-    let colored_eq = "=".blue().bold();
+        let notes = self.notes.iter().map(|x| json_message("note", x, None));
+        let help = self.help.iter().map(|x| json_message("help", x, None));
+        let children: Vec<Value> = notes.chain(help).collect();
 
-    let colored_arrow = "-->".blue().bold();
-    let colored_error_arrow = "^".red().bold();
-    return format!(
-        r"
-{line_padding}{colored_arrow} {colored_error_position}
-{line_padding} {colored_bar}
-{colored_line} {colored_bar} {code}
-{line_padding} {colored_bar}{error_arrow_padding}{colored_error_arrow}
-{line_padding} {colored_bar}
-{line_padding} {colored_eq} {message}
-"
-    );
+        json!({
+            "$message_type": "diagnostic",
+            "message": self.message,
+            "code": {
+                "code": self.code.as_str(),
+                "explanation": null,
+            },
+            "level": self.severity.rustc_level(),
+            "spans": spans,
+            "children": children,
+            "rendered": self.format(),
+        })
+    }
 }
 
-pub fn error(error: pest::error::Error<Rule>) -> ! {
-    eprintln!("{}", format_error(error));
-    std::process::exit(1);
+/// Returned by code that can't go on after an error, once the error is in the `Diagnostics`
+#[derive(Debug, Clone, Copy)]
+pub struct Reported;
+
+/// Errors found so far. Parsing and compiling keep going after an error, so a single run reports
+/// all of them.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    /// Errors that aren't about a place in the code, like a missing main.rl
+    messages: RefCell<Vec<String>>,
+}
+
+impl Diagnostics {
+    pub fn push(&self, diagnostic: Diagnostic) -> Reported {
+        self.diagnostics.borrow_mut().push(diagnostic);
+        Reported
+    }
+
+    pub fn error(&self, code: Code, span: &Span, message: impl ToString) -> Reported {
+        self.push(Diagnostic::new(code, span, message))
+    }
+
+    /// An error without a span, it is reported after the others
+    pub fn message(&self, message: impl ToString) -> Reported {
+        self.messages.borrow_mut().push(message.to_string());
+        Reported
+    }
+
+    /// Takes out every diagnostic found so far
+    pub fn take(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.borrow().len() + self.messages.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Prints every error ordered by file and position, followed by how many there were
+    pub fn report(&self, format: MessageFormat) {
+        let mut diagnostics = self.diagnostics.borrow().clone();
+        diagnostics.sort_by(|a, b| (&a.span.file, a.span.start).cmp(&(&b.span.file, b.span.start)));
+
+        let messages = self.messages.borrow();
+
+        let summary = match self.len() {
+            0 => return,
+            1 => "Could not compile due to the previous error".to_string(),
+            x => format!("Could not compile due to {} previous errors", x),
+        };
+
+        match format {
+            MessageFormat::Human => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", diagnostic.format());
+                }
+            }
+            MessageFormat::Json => {
+                // `rendered` is read by tools, it has no colors
+                colored::control::set_override(false);
+                for diagnostic in &diagnostics {
+                    println!("{}", diagnostic.to_json());
+                }
+            }
+        }
+        for message in messages.iter().chain([&summary]) {
+            format.error(message);
+        }
+    }
 }
 
 pub const ERR_BUG: &str =
     "Unexpected error. This is a bug, please report this at https://github.com/elijah629/redditlang/issues";
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        rc::Rc,
+    };
+
+    use super::{Code, Diagnostic, Diagnostics};
+    use crate::{parse_file, parser::Span};
+
+    /// Writes `source` to a file of its own, diagnostics read the source again to show it
+    fn write_source(name: &str, source: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("walter-{}-{}.rl", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    /// The span of the bytes `start..end` of `source`
+    fn span(file: &Path, source: &str, start: usize, end: usize) -> Span {
+        let before = &source[..start];
+        let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+        Span {
+            file: Rc::from(file),
+            start,
+            end,
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    #[test]
+    fn duplicate_argument_points_at_both() {
+        colored::control::set_override(false);
+        let path = write_source(
+            "duplicate",
+            "callmeonmycellphone twice(x, y, x,) {\n    spez x\n}\n",
+        );
+        let diagnostics = Diagnostics::default();
+        parse_file(&path, &fs::read_to_string(&path).unwrap(), &diagnostics).unwrap();
+        let diagnostics = diagnostics.take();
+        let formatted: Vec<String> = diagnostics.iter().map(|x| x.format()).collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(formatted.len(), 1);
+        assert_eq!(
+            formatted[0],
+            format!(
+                "error[RL0003]: Duplicate argument `x`
+ --> {}:1:33
+  |
+1 | callmeonmycellphone twice(x, y, x,) {{
+  |                           - first used here
+  |                                 ^ used again here
+  |
+  = help: arguments need different names
+",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn long_span_is_shortened() {
+        colored::control::set_override(false);
+        let source = "meth start ∑ 1\nschool Lab {\n\tmeth a ∑ 1\n\tmeth b ∑ 2\n\tmeth c ∑ 3\n\tmeth d ∑ 4\n}\n";
+        let path = write_source("multiline", source);
+        let start = source.find("school").unwrap();
+        let end = source.rfind('}').unwrap() + 1;
+        let name = source.find("Lab").unwrap();
+
+        let diagnostic = Diagnostic::new(
+            Code::InvalidClassMember,
+            &span(&path, source, start, end),
+            "Class `Lab` is wrong",
+        )
+        .with_label("this class")
+        .with_secondary(&span(&path, source, name, name + 3), "named here")
+        .with_note("a note");
+        let formatted = diagnostic.format();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            formatted,
+            format!(
+                "error[RL0018]: Class `Lab` is wrong
+ --> {}:2:1
+  |
+2 | / school Lab {{
+  | |        --- named here
+3 | |     meth a ∑ 1
+...
+6 | |     meth d ∑ 4
+7 | | }}
+  | |_^ this class
+  |
+  = note: a note
+",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn json_counts_characters_from_1() {
+        colored::control::set_override(false);
+        let source = "meth a ∑ 1\nmeth b ∑ \"héllo\" ⨋ a\n";
+        let path = write_source("json", source);
+        let start = source.find('"').unwrap();
+        let end = source.rfind('a').unwrap() + 1;
+        let a = source.find('a').unwrap();
+
+        let diagnostic = Diagnostic::new(
+            Code::InvalidMath,
+            &span(&path, source, start, end),
+            "Math is only supported between two numbers of the same kind",
+        )
+        .with_secondary(&span(&path, source, a, a + 1), "declared here")
+        .with_help("convert it first");
+        let json = diagnostic.to_json();
+        let formatted = diagnostic.format();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(json["$message_type"], "diagnostic");
+        assert_eq!(json["code"]["code"], "RL0016");
+        assert_eq!(json["level"], "error");
+        assert_eq!(json["rendered"], formatted);
+
+        let primary = &json["spans"][0];
+        assert_eq!(primary["is_primary"], true);
+        assert_eq!(primary["line_start"], 2);
+        assert_eq!(primary["line_end"], 2);
+        // `meth b ∑ ` is 9 characters but 11 bytes
+        assert_eq!(primary["column_start"], 10);
+        assert_eq!(primary["column_end"], 21);
+        assert_eq!(primary["text"][0]["text"], "meth b ∑ \"héllo\" ⨋ a");
+        assert_eq!(primary["text"][0]["highlight_start"], 10);
+        assert_eq!(primary["text"][0]["highlight_end"], 21);
+
+        let secondary = &json["spans"][1];
+        assert_eq!(secondary["is_primary"], false);
+        assert_eq!(secondary["label"], "declared here");
+        assert_eq!(secondary["line_start"], 1);
+        assert_eq!(secondary["column_start"], 6);
+        assert_eq!(secondary["column_end"], 7);
+
+        assert_eq!(json["children"][0]["level"], "help");
+        assert_eq!(json["children"][0]["message"], "convert it first");
+        assert_eq!(json["children"][0]["spans"].as_array().unwrap().len(), 0);
+    }
+}
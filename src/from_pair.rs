@@ -1,350 +1,592 @@
-use crate::errors::{error, ERR_BUG};
+use crate::errors::{Code, Diagnostic, Diagnostics};
 use crate::parser::{
     parse, parse_one, Assignment, BinaryExpr, BinaryExprTerm, Break, Call, Catch, Class,
     ConditionExprTerm, ConditionalExpr, ConditionalOperator, Declaration, Else, ElseIf, Expr,
-    Function, FunctionMod, Ident, If, IfBlock, IfNode, Import, Index, IndexExpr, Loop,
-    MathOperator, Module, Node, Return, Term, Throw, Tree, Try, TryCatch, Type, Variable,
+    Foolean, Function, FunctionMod, Ident, If, IfBlock, IfNode, Import, Index, IndexExpr, Loop,
+    MathOperator, Module, Node, Return, Span, Term, Throw, Tree, Try, TryCatch, Type, Variable,
     VariableMod,
 };
-use crate::utils::is_unique;
+use crate::utils::duplicates;
 use crate::Rule;
-use pest::error::Error;
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The file a tree is parsed from and where the errors found in it go. Errors that the tree can be
+/// built around are reported here, the tree is still returned.
+pub struct ParseContext<'a> {
+    /// Every span points into this file
+    pub file: Rc<Path>,
+    pub diagnostics: &'a Diagnostics,
+}
+
+impl<'a> ParseContext<'a> {
+    pub fn new(file: &Path, diagnostics: &'a Diagnostics) -> Self {
+        Self {
+            file: Rc::from(file),
+            diagnostics,
+        }
+    }
+}
+
+/// A pair that doesn't have the shape the grammar gives it. This is a bug in walter rather than in
+/// the program, it is reported as an internal error at the code that was being parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(span: &Span, message: impl ToString) -> Self {
+        Self {
+            span: span.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic::internal(&error.span, error.message)
+    }
+}
+
+/// The next child of the pair at `span`, which the grammar says is there
+fn next<'a>(inner: &mut Pairs<'a, Rule>, span: &Span) -> Result<Pair<'a, Rule>, ParseError> {
+    inner
+        .next()
+        .ok_or_else(|| ParseError::new(span, "MISSING_PAIR"))
+}
+
+pub fn span_of(pair: &Pair<'_, Rule>, context: &ParseContext) -> Span {
+    let span = pair.as_span();
+    let (line, col) = span.start_pos().line_col();
+    Span {
+        file: context.file.clone(),
+        start: span.start(),
+        end: span.end(),
+        line,
+        col,
+    }
+}
 
 pub trait Parse {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self>
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError>
     where
         Self: Sized;
 }
 
 impl Parse for Declaration {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let ident = Ident::parse_from(inner.next().unwrap()).unwrap();
-        let r#type = inner.next().map(|x| x.into_inner()).map(|mut x| Type {
-            ident: Ident::parse_from(x.next().unwrap()).unwrap(),
-            is_array: x.next().is_some(),
-        });
+        let ident = Ident::parse_from(next(&mut inner, &span)?, context)?;
+        let r#type = match inner.next() {
+            Some(x) => {
+                let span = span_of(&x, context);
+                let mut inner = x.into_inner();
+                Some(Type {
+                    ident: Ident::parse_from(next(&mut inner, &span)?, context)?,
+                    is_array: inner.next().is_some(),
+                })
+            }
+            None => None,
+        };
 
-        Some(Self { ident, r#type })
+        Ok(Self { ident, r#type })
     }
 }
 
 impl Parse for Function {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        let start_pos = pair.as_span().start_pos();
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let modifiers: Vec<FunctionMod> = inner
-            .next()
-            .unwrap()
+        let modifiers: Vec<FunctionMod> = next(&mut inner, &span)?
             .into_inner()
-            .map(|modifier| modifier.as_str().trim_end().to_string())
-            .map(|modifier| match modifier.as_str() {
-                "debug" => FunctionMod::Debug,
-                "bar" => FunctionMod::Public,
-                _ => error(Error::new_from_pos(
-                    pest::error::ErrorVariant::CustomError {
-                        message: "Invalid modifier".to_owned(),
-                    },
-                    start_pos,
-                )),
+            .filter_map(|modifier| match modifier.as_str().trim_end() {
+                "debug" => Some(FunctionMod::Debug),
+                "bar" => Some(FunctionMod::Public),
+                _ => {
+                    context.diagnostics.push(Diagnostic::new(
+                        Code::InvalidModifier,
+                        &span_of(&modifier, context),
+                        format!("Invalid modifier `{}`", modifier.as_str().trim_end()),
+                    ));
+                    None
+                }
             })
             .collect();
 
-        let declaration = Declaration::parse_from(inner.next().unwrap()).unwrap();
+        let declaration = Declaration::parse_from(next(&mut inner, &span)?, context)?;
 
-        let raw_args = inner.next().unwrap();
-        let start_pos = raw_args.as_span().start_pos();
-        let args: Vec<Declaration> = raw_args
+        let args = next(&mut inner, &span)?
             .into_inner()
-            .map(|x| Declaration::parse_from(x).unwrap())
-            .collect();
-
-        let has_duplicates = !is_unique(args.iter().map(|x| &x.ident.0));
-        if has_duplicates {
-            error(Error::new_from_pos(
-                pest::error::ErrorVariant::CustomError {
-                    message: "Duplicate arguments".to_owned(),
-                },
-                start_pos,
-            ))
+            .map(|x| Declaration::parse_from(x, context))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (first, again) in duplicates(args.iter().map(|x| &x.ident.0)) {
+            let (first, again) = (&args[first].ident, &args[again].ident);
+            context.diagnostics.push(
+                Diagnostic::new(
+                    Code::DuplicateArgument,
+                    &again.1,
+                    format!("Duplicate argument `{}`", again.0),
+                )
+                .with_label("used again here")
+                .with_secondary(&first.1, "first used here")
+                .with_help("arguments need different names"),
+            );
         }
-        let body = Tree::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self {
+        let body = Tree::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self {
             modifiers,
             declaration,
             args,
             body,
+            span,
         })
     }
 }
 
 impl Parse for Term {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         match pair.as_rule() {
-            Rule::String => Some(Self::String(
-                enquote::unquote(pair.as_str()).unwrap().to_string(),
-            )),
-            Rule::Number => Some(Self::Number(pair.as_str().parse().unwrap())),
-            Rule::Ident => Some(Self::Ident(Ident::parse_from(pair).unwrap())),
-            _ => None,
+            Rule::String => match enquote::unquote(pair.as_str()) {
+                Ok(x) => Ok(Self::String(x, span)),
+                Err(x) => Err(ParseError::new(&span, format!("INVALID_STRING({:?})", x))),
+            },
+            Rule::Number => match pair.as_str().parse() {
+                Ok(x) => Ok(Self::Number(x, span)),
+                Err(_) => {
+                    context.diagnostics.push(
+                        Diagnostic::new(Code::InvalidNumber, &span, "Invalid number")
+                            .with_note("numbers must be whole and fit in 128 bits"),
+                    );
+                    Ok(Self::Number(0, span))
+                }
+            },
+            Rule::Boolean | Rule::Foolean => {
+                let value = match pair.into_inner().next().map(|x| x.as_rule()) {
+                    Some(Rule::True) => Foolean::Yup,
+                    Some(Rule::False) => Foolean::Nope,
+                    Some(Rule::FooleanNull) => Foolean::Dunno,
+                    Some(Rule::FooleanIOFalure) => Foolean::Huh,
+                    Some(Rule::FooleanRandom) => Foolean::Yeet,
+                    x => return Err(ParseError::new(&span, format!("NOT_A_FOOLEAN({:?})", x))),
+                };
+                Ok(Self::Boolean(value, span))
+            }
+            Rule::Ident => Ok(Self::Ident(Ident::parse_from(pair, context)?)),
+            Rule::Call => Ok(Self::Call(Call::parse_from(pair, context)?)),
+            Rule::Expr => Ok(Self::Expr(Box::new(Expr::parse_from(pair, context)?))),
+            x => Err(ParseError::new(&span, format!("NOT_A_TERM({:?})", x))),
         }
     }
 }
 
 impl Parse for Module {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let ident = Ident::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self { ident })
+        let ident = Ident::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self { ident, span })
     }
 }
 
 impl Parse for Call {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let ident = Ident::parse_from(inner.next().unwrap()).unwrap();
-        let args = inner
-            .next()
-            .unwrap()
-            .into_inner()
-            .map(|x| Term::parse_from(x.into_inner().next().unwrap()).unwrap())
-            .collect();
-        Some(Self { ident, args })
+        let first = Ident::parse_from(next(&mut inner, &span)?, context)?;
+        let (receiver, ident) = match inner.peek().map(|x| x.as_rule()) {
+            Some(Rule::Ident) => (
+                Some(first),
+                Ident::parse_from(next(&mut inner, &span)?, context)?,
+            ),
+            _ => (None, first),
+        };
+        let args = match inner.next() {
+            Some(x) => x
+                .into_inner()
+                .map(|x| Expr::parse_from(x, context))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+        };
+        Ok(Self {
+            receiver,
+            ident,
+            args,
+            span,
+        })
     }
 }
 
 impl Parse for Break {
-    fn parse_from(_pair: Pair<'_, Rule>) -> Option<Self> {
-        Some(Break)
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        Ok(Break {
+            span: span_of(&pair, context),
+        })
     }
 }
 
 impl Parse for Throw {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let value = Expr::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self { value })
+        let value = Expr::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self { value, span })
     }
 }
 
 impl Parse for Import {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let path = Term::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self { path })
+        let path = Term::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self { path, span })
     }
 }
 
 impl Parse for Loop {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        Some(Self {
-            body: Tree::parse_from(inner.next().unwrap()).unwrap(),
+        Ok(Self {
+            body: Tree::parse_from(next(&mut inner, &span)?, context)?,
+            span,
         })
     }
 }
 
 impl Parse for TryCatch {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
 
-        let r#try = Try(Tree::parse_from(inner.next().unwrap()).unwrap());
-        let mut catch = inner.next().unwrap().into_inner();
+        let r#try = Try(Tree::parse_from(next(&mut inner, &span)?, context)?);
+        let catch = next(&mut inner, &span)?;
+        let catch_span = span_of(&catch, context);
+        let mut catch = catch.into_inner();
 
-        let first = catch.next().unwrap();
+        let first = next(&mut catch, &catch_span)?;
         let catch = match first.as_rule() {
-            Rule::Block => Catch(None, Tree::parse_from(first).unwrap()),
+            Rule::Block => Catch(None, Tree::parse_from(first, context)?),
             Rule::Ident => Catch(
-                Ident::parse_from(first),
-                Tree::parse_from(catch.next().unwrap()).unwrap(),
-            ),
-            _ => panic!(
-                "{} Additional Information: NOT_CATCH_OR_IDENT({:?})",
-                ERR_BUG,
-                first.as_rule()
+                Some(Ident::parse_from(first, context)?),
+                Tree::parse_from(next(&mut catch, &catch_span)?, context)?,
             ),
+            x => {
+                return Err(ParseError::new(
+                    &span_of(&first, context),
+                    format!("NOT_CATCH_OR_IDENT({:?})", x),
+                ))
+            }
         };
-        Some(TryCatch { r#try, catch })
+        Ok(TryCatch { r#try, catch, span })
     }
 }
 
 impl Parse for Variable {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        let start_pos = pair.as_span().start_pos();
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let modifiers: Vec<VariableMod> = inner
-            .next()
-            .unwrap()
+        let modifiers: Vec<VariableMod> = next(&mut inner, &span)?
             .into_inner()
-            .map(|modifier| modifier.as_str().trim_end().to_string())
-            .map(|modifier| match modifier.as_str() {
-                "bar" => VariableMod::Public,
-                _ => error(Error::new_from_pos(
-                    pest::error::ErrorVariant::CustomError {
-                        message: "Invalid modifier".to_owned(),
-                    },
-                    start_pos,
-                )),
+            .filter_map(|modifier| match modifier.as_str().trim_end() {
+                "bar" => Some(VariableMod::Public),
+                _ => {
+                    context.diagnostics.push(Diagnostic::new(
+                        Code::InvalidModifier,
+                        &span_of(&modifier, context),
+                        format!("Invalid modifier `{}`", modifier.as_str().trim_end()),
+                    ));
+                    None
+                }
             })
             .collect();
-        let declaration = Declaration::parse_from(inner.next().unwrap()).unwrap();
-        let value = Expr::parse_from(inner.next().unwrap()).unwrap();
+        let declaration = Declaration::parse_from(next(&mut inner, &span)?, context)?;
+        let value = Expr::parse_from(next(&mut inner, &span)?, context)?;
 
-        Some(Self {
+        Ok(Self {
             modifiers,
             declaration,
             value,
+            span,
         })
     }
 }
 
+/// The rule of the operator inside of a `MathOperator` or `ConditionalOperator` pair
+fn operator(pair: &Pair<'_, Rule>, context: &ParseContext) -> Result<Rule, ParseError> {
+    let span = span_of(pair, context);
+    Ok(next(&mut pair.clone().into_inner(), &span)?.as_rule())
+}
+
 impl Parse for BinaryExpr {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        Some(Self {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        Ok(Self {
+            span: span_of(&pair, context),
             terms: pair
                 .into_inner()
                 .collect::<Vec<_>>()
                 .chunks(2)
-                .map(|x| BinaryExprTerm {
-                    operand: Term::parse_from((x[0]).clone()).unwrap(),
-                    operator: x.get(1).and_then(|x| {
-                        match x.clone().into_inner().next().unwrap().as_rule() {
-                            Rule::Subtract => Some(MathOperator::Subtract),
-                            Rule::Multiply => Some(MathOperator::Multiply),
-                            Rule::Divide => Some(MathOperator::Divide),
-                            Rule::XOR => Some(MathOperator::XOR),
-                            _ => panic!("Unknown operator"),
-                        }
-                    }),
+                .map(|x| {
+                    Ok(BinaryExprTerm {
+                        operand: Term::parse_from((x[0]).clone(), context)?,
+                        operator: match x.get(1) {
+                            Some(x) => Some(match operator(x, context)? {
+                                Rule::Add => MathOperator::Add,
+                                Rule::Subtract => MathOperator::Subtract,
+                                Rule::Multiply => MathOperator::Multiply,
+                                Rule::Divide => MathOperator::Divide,
+                                Rule::XOR => MathOperator::XOR,
+                                rule => {
+                                    return Err(ParseError::new(
+                                        &span_of(x, context),
+                                        format!("UNKNOWN_OPERATOR({:?})", rule),
+                                    ))
+                                }
+                            }),
+                            None => None,
+                        },
+                    })
                 })
-                .collect::<Vec<_>>(),
+                .collect::<Result<Vec<_>, _>>()?,
         })
     }
 }
 
 impl Parse for ConditionalExpr {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        Some(Self {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        Ok(Self {
+            span: span_of(&pair, context),
             terms: pair
                 .into_inner()
                 .collect::<Vec<_>>()
                 .chunks(2)
-                .map(|x| ConditionExprTerm {
-                    operand: Term::parse_from((x[0]).clone()).unwrap(),
-                    operator: x.get(1).and_then(|x| {
-                        match x.clone().into_inner().next().unwrap().as_rule() {
-                            Rule::Equality => Some(ConditionalOperator::Equality),
-                            Rule::AntiEquality => Some(ConditionalOperator::AntiEquality),
-                            _ => panic!("Unknown operator"),
-                        }
-                    }),
+                .map(|x| {
+                    Ok(ConditionExprTerm {
+                        operand: Term::parse_from((x[0]).clone(), context)?,
+                        operator: match x.get(1) {
+                            Some(x) => Some(match operator(x, context)? {
+                                Rule::Equality => ConditionalOperator::Equality,
+                                Rule::AntiEquality => ConditionalOperator::AntiEquality,
+                                rule => {
+                                    return Err(ParseError::new(
+                                        &span_of(x, context),
+                                        format!("UNKNOWN_OPERATOR({:?})", rule),
+                                    ))
+                                }
+                            }),
+                            None => None,
+                        },
+                    })
                 })
-                .collect::<Vec<_>>(),
+                .collect::<Result<Vec<_>, _>>()?,
         })
     }
 }
 
 impl Parse for Assignment {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let ident = Ident::parse_from(inner.next().unwrap()).unwrap();
-        let value = Expr::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self { ident, value })
+        let ident = Ident::parse_from(next(&mut inner, &span)?, context)?;
+        let value = Expr::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self { ident, value, span })
     }
 }
 
 impl Parse for Ident {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        Some(Self(pair.as_str().to_string()))
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        Ok(Self(pair.as_str().to_string(), span_of(&pair, context)))
     }
 }
 
 impl Parse for Expr {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        let start_pos = pair.as_span().start_pos();
-        let value = parse_one(pair).expect("Invalid expression");
-        match value {
-            Node::Expr(x) => Some(x),
-            _ => error(Error::new_from_pos(
-                pest::error::ErrorVariant::CustomError {
-                    message: "Value is not an expression".to_owned(),
-                },
-                start_pos,
-            )),
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
+        match parse_one(pair, context)? {
+            Some(Node::Expr(x)) => Ok(x),
+            Some(_) => {
+                context.diagnostics.push(Diagnostic::new(
+                    Code::NotAnExpression,
+                    &span,
+                    "Value is not an expression",
+                ));
+                Ok(Expr::Null(span))
+            }
+            None => Err(ParseError::new(&span, "NOT_AN_EXPRESSION")),
         }
     }
 }
 
 impl Parse for Tree {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        Some(parse(pair.into_inner()))
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        parse(pair.into_inner(), context)
     }
 }
 
 impl Parse for IfBlock {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
-        fn if_node(pair: Pair<'_, Rule>) -> IfNode {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        fn if_node(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<IfNode, ParseError> {
+            let span = span_of(&pair, context);
             let rule = pair.as_rule();
             let mut inner = pair.into_inner();
-            match rule {
+            Ok(match rule {
                 Rule::If => IfNode::If(If {
-                    expr: Expr::parse_from(inner.next().unwrap()).unwrap(),
-                    body: Tree::parse_from(inner.next().unwrap()).unwrap(),
+                    expr: Expr::parse_from(next(&mut inner, &span)?, context)?,
+                    body: Tree::parse_from(next(&mut inner, &span)?, context)?,
                 }),
                 Rule::ElseIf => IfNode::ElseIf(ElseIf {
-                    expr: Expr::parse_from(inner.next().unwrap()).unwrap(),
-                    body: Tree::parse_from(inner.next().unwrap()).unwrap(),
+                    expr: Expr::parse_from(next(&mut inner, &span)?, context)?,
+                    body: Tree::parse_from(next(&mut inner, &span)?, context)?,
                 }),
                 Rule::Else => IfNode::Else(Else {
-                    body: Tree::parse_from(inner.next().unwrap()).unwrap(),
+                    body: Tree::parse_from(next(&mut inner, &span)?, context)?,
                 }),
-                _ => panic!("{}", ERR_BUG),
-            }
+                x => return Err(ParseError::new(&span, format!("NOT_AN_IF({:?})", x))),
+            })
         }
 
-        let if_nodes: Vec<IfNode> = pair
+        let span = span_of(&pair, context);
+        let if_nodes = pair
             .into_inner()
-            .map(|x| match x.as_rule() {
-                Rule::If | Rule::ElseIf | Rule::Else => if_node(x),
-                _ => panic!("{}", ERR_BUG),
-            })
-            .collect();
+            .map(|x| if_node(x, context))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Some(Self { if_nodes })
+        Ok(Self { if_nodes, span })
     }
 }
 
 impl Parse for Return {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
-        let value = Expr::parse_from(inner.next().unwrap()).unwrap();
-        Some(Self { value })
+        let value = Expr::parse_from(next(&mut inner, &span)?, context)?;
+        Ok(Self { value, span })
     }
 }
 
 impl Parse for Class {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
 
-        let ident = Ident::parse_from(inner.next().unwrap()).unwrap();
-        let body = Tree::parse_from(inner.next().unwrap()).unwrap();
+        let ident = Ident::parse_from(next(&mut inner, &span)?, context)?;
+        let body = next(&mut inner, &span)?;
 
-        Some(Self { ident, body })
+        // Constructors and destructors only work with their exact names, catch near misses
+        for statement in body.clone().into_inner() {
+            let statement_span = span_of(&statement, context);
+            let function = next(&mut statement.into_inner(), &statement_span)?;
+            if function.as_rule() != Rule::Function {
+                continue;
+            }
+
+            let function_span = span_of(&function, context);
+            let mut function = function.into_inner();
+            let _modifiers = function.next();
+            let declaration = next(&mut function, &function_span)?;
+            let declaration_span = span_of(&declaration, context);
+            let name = next(&mut declaration.into_inner(), &declaration_span)?;
+            let args = next(&mut function, &function_span)?;
+
+            let misnamed = match name.as_str() {
+                "cooK" | "snoRt" => None,
+                x if x == ident.0 || x.eq_ignore_ascii_case("cook") => {
+                    Some(("Constructors", "cooK"))
+                }
+                x if x.eq_ignore_ascii_case("snort") => Some(("Destructors", "snoRt")),
+                _ => None,
+            };
+            if let Some((kind, expected)) = misnamed {
+                context.diagnostics.push(
+                    Diagnostic::new(
+                        Code::InvalidSpecialMethod,
+                        &span_of(&name, context),
+                        format!(
+                            "{} must be named `{}`, found `{}`",
+                            kind,
+                            expected,
+                            name.as_str()
+                        ),
+                    )
+                    .with_help(format!("rename it to `{}`", expected)),
+                );
+            }
+
+            if name.as_str() == "snoRt" && args.clone().into_inner().next().is_some() {
+                context.diagnostics.push(
+                    Diagnostic::new(
+                        Code::InvalidSpecialMethod,
+                        &span_of(&args, context),
+                        "Destructors can't take arguments",
+                    )
+                    .with_secondary(&span_of(&name, context), "destructor declared here"),
+                );
+            }
+        }
+
+        let body = Tree::parse_from(body, context)?;
+
+        Ok(Self { ident, body, span })
     }
 }
 
 impl Parse for IndexExpr {
-    fn parse_from(pair: Pair<'_, Rule>) -> Option<Self> {
+    fn parse_from(pair: Pair<'_, Rule>, context: &ParseContext) -> Result<Self, ParseError> {
+        let span = span_of(&pair, context);
         let mut inner = pair.into_inner();
 
-        let term = Term::parse_from(inner.next().unwrap()).unwrap();
-        let index = Term::parse_from(inner.next().unwrap()).unwrap();
+        let term = Term::parse_from(next(&mut inner, &span)?, context)?;
+        let index = Term::parse_from(next(&mut inner, &span)?, context)?;
         let index = match index {
-            Term::Number(x) => Index::Number(x),
-            Term::String(x) => Index::String(x),
-            _ => panic!("{:?}", ERR_BUG),
+            Term::Number(x, _) => Index::Number(x),
+            Term::String(x, _) => Index::String(x),
+            x => return Err(ParseError::new(x.span(), "NOT_AN_INDEX")),
         };
-        Some(Self { term, index })
+        Ok(Self { term, index, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pest::Parser;
+
+    use super::{Parse, ParseContext};
+    use crate::errors::{Code, Diagnostic, Diagnostics, Severity};
+    use crate::parser::Declaration;
+    use crate::{RLParser, Rule};
+
+    #[test]
+    fn malformed_pair_is_an_internal_error_at_its_span() {
+        let source = "count";
+        let pair = RLParser::parse(Rule::Ident, source)
+            .unwrap()
+            .next()
+            .unwrap();
+        let diagnostics = Diagnostics::default();
+        let context = ParseContext::new(Path::new("main.rl"), &diagnostics);
+
+        // An identifier has no children, a declaration needs one
+        let error = Declaration::parse_from(pair, &context).unwrap_err();
+        assert_eq!(error.message, "MISSING_PAIR");
+        assert!(diagnostics.is_empty());
+
+        let diagnostic = Diagnostic::from(error);
+        assert_eq!(diagnostic.code, Code::Internal);
+        assert_eq!(diagnostic.severity, Severity::InternalError);
+        assert_eq!(&*diagnostic.span.file, Path::new("main.rl"));
+        assert_eq!(
+            (diagnostic.span.start, diagnostic.span.end),
+            (0, source.len())
+        );
+        assert_eq!((diagnostic.span.line, diagnostic.span.col), (1, 1));
+        assert!(diagnostic.notes.iter().any(|x| x.ends_with("MISSING_PAIR")));
     }
 }
@@ -0,0 +1,123 @@
+use std::{fmt, process::Command};
+
+use colored::Colorize;
+
+/// A link command that could not be run or did not succeed
+#[derive(Debug)]
+pub struct LinkError {
+    pub command: String,
+    pub message: String,
+    /// Output of the linker, empty if it couldn't be started
+    pub stderr: String,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colored_bar = "|".blue().bold();
+        let colored_eq = "=".blue().bold();
+        let colored_arrow = "-->".blue().bold();
+
+        writeln!(f, "{} {}", "Linking failed:".red().bold(), self.message)?;
+        writeln!(f, " {} {}", colored_arrow, self.command)?;
+        if !self.stderr.trim().is_empty() {
+            writeln!(f, "  {}", colored_bar)?;
+            for line in self.stderr.trim_end().lines() {
+                writeln!(f, "  {} {}", colored_bar, line)?;
+            }
+        }
+        writeln!(f, "  {}", colored_bar)?;
+        write!(
+            f,
+            "  {} {} the linker can be changed with {} or `linker` in walter.yml",
+            colored_eq,
+            "help:".bold(),
+            "--linker".bold()
+        )
+    }
+}
+
+/// The command line as it would be typed into a shell
+fn display_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|x| x.to_string_lossy())
+        .map(|x| {
+            if x.contains(char::is_whitespace) {
+                format!("'{}'", x)
+            } else {
+                x.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs a link command and waits for it to finish
+pub fn link(mut command: Command) -> Result<(), LinkError> {
+    let output = command.output().map_err(|x| LinkError {
+        command: display_command(&command),
+        message: x.to_string(),
+        stderr: String::new(),
+    })?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(LinkError {
+        command: display_command(&command),
+        message: match output.status.code() {
+            Some(x) => format!("the linker exited with code {}", x),
+            None => "the linker was killed".to_string(),
+        },
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::link;
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_link_keeps_the_exit_code_and_output() {
+        colored::control::set_override(false);
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'undefined reference to `spez`' >&2; exit 3"]);
+
+        let error = link(command).unwrap_err();
+        assert_eq!(
+            error.command,
+            "sh -c 'echo 'undefined reference to `spez`' >&2; exit 3'"
+        );
+        assert_eq!(error.message, "the linker exited with code 3");
+        assert_eq!(error.stderr, "undefined reference to `spez`\n");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Linking failed: the linker exited with code 3
+ --> {}
+  |
+  | undefined reference to `spez`
+  |
+  = help: the linker can be changed with --linker or `linker` in walter.yml",
+                error.command
+            )
+        );
+    }
+
+    #[test]
+    fn missing_linker_is_an_error() {
+        let error = link(Command::new("walter-no-such-linker")).unwrap_err();
+        assert_eq!(error.command, "walter-no-such-linker");
+        assert!(error.stderr.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn successful_link() {
+        assert!(link(Command::new("true")).is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+//! Bullets use an explicit error-return convention instead of landing pads. `shoot` stores the
+//! bullet in a global and jumps to the innermost `wall` of the current function. If there is none
+//! the function returns early, and every call to a RedditLang function checks the global
+//! afterwards so the bullet keeps travelling up the stack. `main` reports bullets nobody caught,
+//! together with the position of the `shoot` that fired them.
+
+use inkwell::{
+    module::Linkage,
+    targets::TargetData,
+    types::FunctionType,
+    values::{BasicMetadataValueEnum, FunctionValue, PointerValue},
+    IntPredicate,
+};
+
+use crate::parser::Span;
+
+use super::{zero_value, Compiler};
+
+const BULLET_GLOBAL: &str = "__rl_bullet";
+const ORIGIN_GLOBAL: &str = "__rl_bullet_origin";
+
+/// Bytes of the messages printed on Windows, including the terminating zero
+const MESSAGE_BUFFER: u32 = 1024;
+
+/// Exit code of a program killed by a bullet nobody caught
+pub const UNCAUGHT_EXIT_CODE: u64 = 1;
+
+impl<'ctx> Compiler<'ctx> {
+    /// The global holding the bullet in flight, null if there is none
+    pub fn bullet_global(&self) -> PointerValue<'ctx> {
+        self.string_global(BULLET_GLOBAL)
+    }
+
+    fn string_global(&self, name: &str) -> PointerValue<'ctx> {
+        let global = match self.module.get_global(name) {
+            Some(x) => x,
+            None => {
+                let global = self.module.add_global(self.string_type(), None, name);
+                global.set_initializer(&self.string_type().const_null());
+                global.set_linkage(Linkage::Internal);
+                global
+            }
+        };
+        global.as_pointer_value()
+    }
+
+    /// Remembers where the bullet in flight was shot, for the report if nobody catches it
+    pub fn build_store_bullet_origin(&self, span: &Span) {
+        let origin = self
+            .builder
+            .build_global_string_ptr(&span.to_string(), ".origin")
+            .as_pointer_value();
+        self.builder
+            .build_store(self.string_global(ORIGIN_GLOBAL), origin);
+    }
+
+    pub fn build_load_bullet(&self) -> PointerValue<'ctx> {
+        self.builder
+            .build_load(self.string_type(), self.bullet_global(), "bullet")
+            .into_pointer_value()
+    }
+
+    /// Moves the bullet in flight to the nearest `wall`, out of the current function if needed.
+    /// This always ends the current block.
+    pub fn build_unwind(&self) {
+        if let Some(x) = self.catches.borrow().last() {
+            self.builder.build_unconditional_branch(*x);
+            return;
+        }
+
+        let function = self.current_function();
+        if function.get_name().to_str() == Ok("main") {
+            self.build_uncaught();
+            return;
+        }
+
+        match function.get_type().get_return_type() {
+            Some(x) => self.builder.build_return(Some(&zero_value(x))),
+            None => self.builder.build_return(None),
+        };
+    }
+
+    /// Unwinds if the function that was just called let a bullet escape
+    pub fn build_bullet_check(&self) {
+        let function = self.current_function();
+        let hit = self.context.append_basic_block(function, "hit");
+        let missed = self.context.append_basic_block(function, "missed");
+
+        let bullet = self.build_load_bullet();
+        let is_hit = self.builder.build_is_not_null(bullet, "is_hit");
+        self.builder.build_conditional_branch(is_hit, hit, missed);
+
+        self.builder.position_at_end(hit);
+        self.build_unwind();
+
+        self.builder.position_at_end(missed);
+    }
+
+    /// A function from libc, declared the first time it is used
+    fn libc_function(&self, name: &str, r#type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, r#type, None))
+    }
+
+    /// Prints to stderr like `fprintf(stderr, format, args...)`. That is `dprintf` from libc, which
+    /// Windows doesn't have, so there the message is formatted into a buffer and written with
+    /// `_write`. Longer messages are cut off.
+    pub fn build_eprintf(
+        &self,
+        format: PointerValue<'ctx>,
+        args: &[BasicMetadataValueEnum<'ctx>],
+        name: &str,
+    ) {
+        let i32_type = self.context.i32_type();
+        let stderr = i32_type.const_int(2, false);
+
+        let is_windows = self
+            .module
+            .get_triple()
+            .as_str()
+            .to_string_lossy()
+            .contains("windows");
+        if !is_windows {
+            let r#type = i32_type.fn_type(&[i32_type.into(), self.string_type().into()], true);
+            let mut all_args = vec![stderr.into(), format.into()];
+            all_args.extend_from_slice(args);
+            self.builder
+                .build_call(self.libc_function("dprintf", r#type), &all_args, name);
+            return;
+        }
+
+        let data_layout = self.module.get_data_layout();
+        let target_data = TargetData::create(data_layout.as_str().to_str().unwrap());
+        let size_type = self.context.ptr_sized_int_type(&target_data, None);
+
+        let buffer_type = self.context.i8_type().array_type(MESSAGE_BUFFER);
+        let buffer = self.build_entry_alloca(buffer_type.into(), "message");
+        let buffer = self
+            .builder
+            .build_pointer_cast(buffer, self.string_type(), "message");
+
+        let snprintf_type = i32_type.fn_type(
+            &[
+                self.string_type().into(),
+                size_type.into(),
+                self.string_type().into(),
+            ],
+            true,
+        );
+        let mut all_args = vec![
+            buffer.into(),
+            size_type.const_int(MESSAGE_BUFFER as u64, false).into(),
+            format.into(),
+        ];
+        all_args.extend_from_slice(args);
+        let length = self
+            .builder
+            .build_call(
+                self.libc_function("snprintf", snprintf_type),
+                &all_args,
+                name,
+            )
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+
+        // The length of the whole message is returned even if it was cut off
+        let max = i32_type.const_int(MESSAGE_BUFFER as u64 - 1, false);
+        let is_cut = self
+            .builder
+            .build_int_compare(IntPredicate::SGT, length, max, "is_cut");
+        let length = self
+            .builder
+            .build_select(is_cut, max, length, "length")
+            .into_int_value();
+
+        let write_type = i32_type.fn_type(
+            &[i32_type.into(), self.string_type().into(), i32_type.into()],
+            false,
+        );
+        self.builder.build_call(
+            self.libc_function("_write", write_type),
+            &[stderr.into(), buffer.into(), length.into()],
+            name,
+        );
+    }
+
+    /// Prints the bullet and where it was shot to stderr, then exits `main` with
+    /// `UNCAUGHT_EXIT_CODE`
+    fn build_uncaught(&self) {
+        let format = self
+            .builder
+            .build_global_string_ptr("Uncaught bullet: %s\n --> %s\n", ".str")
+            .as_pointer_value();
+        let origin = self.builder.build_load(
+            self.string_type(),
+            self.string_global(ORIGIN_GLOBAL),
+            "origin",
+        );
+        let args: [BasicMetadataValueEnum; 2] = [self.build_load_bullet().into(), origin.into()];
+        self.build_eprintf(format, &args, "report");
+
+        self.builder.build_return(Some(
+            &self.context.i32_type().const_int(UNCAUGHT_EXIT_CODE, false),
+        ));
+    }
+}
@@ -0,0 +1,466 @@
+//! A `school` compiles to a heap allocated struct. Its methods become functions named
+//! `Class.method` taking the object as a hidden first argument, which is available as `self`.
+//! Field initializers are compiled into a `Class.<init>` function that runs before `cooK`.
+//!
+//! A variable that is assigned a freshly constructed object owns it, and the object is destroyed
+//! with `snoRt` when that variable's block ends or the function returns. Bullets skip destructors.
+
+use inkwell::{
+    types::{BasicTypeEnum, StructType},
+    values::{BasicMetadataValueEnum, BasicValueEnum, FunctionValue, PointerValue},
+    IntPredicate,
+};
+
+use crate::{
+    errors::{Code, Diagnostic, Reported},
+    parser::{Call, Class, Expr, Node, Span, Term, VariableMod},
+};
+
+use super::{
+    compile_node::{check_args, compile_function_body, declare_function_as, Compile, CompileValue},
+    symbols::Symbol,
+    zero_value, Compiler,
+};
+
+#[derive(Debug)]
+pub struct Field<'ctx> {
+    pub name: String,
+    pub r#type: BasicTypeEnum<'ctx>,
+    /// The class of the object this field points to, if any
+    pub class: Option<String>,
+    /// Fields are private by default
+    pub public: bool,
+}
+
+#[derive(Debug)]
+pub struct ClassInfo<'ctx> {
+    pub struct_type: StructType<'ctx>,
+    pub fields: Vec<Field<'ctx>>,
+    /// The name of the class where it is defined, other classes with the same name are not compiled
+    pub span: Span,
+}
+
+/// The method that sets the fields of a new object, it can't be written as an identifier so it
+/// doesn't collide with a method of the class
+const INITIALIZER: &str = "<init>";
+
+/// The LLVM name of a method
+pub fn method_name(class: &str, method: &str) -> String {
+    format!("{}.{}", class, method)
+}
+
+/// Registers the name of a class, so fields and signatures can refer to it before its layout is known
+pub fn declare_class(compiler: &Compiler, class: &Class) -> Result<(), Reported> {
+    let name = class.ident.0.as_str();
+    let llvm_name = compiler.mangle(name);
+    if let Some(first) = compiler.classes.borrow().get(&llvm_name) {
+        return Err(compiler.diagnostics.push(
+            Diagnostic::new(
+                Code::DuplicateDefinition,
+                &class.ident.1,
+                format!("Class `{}` is already defined", name),
+            )
+            .with_label("defined again here")
+            .with_secondary(&first.span, "first defined here"),
+        ));
+    }
+
+    let info = ClassInfo {
+        struct_type: compiler.context.opaque_struct_type(&llvm_name),
+        fields: vec![],
+        span: class.ident.1.clone(),
+    };
+    compiler
+        .classes
+        .borrow_mut()
+        .insert(llvm_name.clone(), info);
+    // Hides a class of the same name from an imported file
+    compiler
+        .class_names
+        .borrow_mut()
+        .insert(name.to_string(), llvm_name);
+    Ok(())
+}
+
+/// Lays out the fields of a class and declares its methods. Fields and methods with errors are
+/// left out.
+pub fn define_class(compiler: &Compiler, class: &Class) {
+    let name = class.ident.0.as_str();
+    let llvm_name = compiler.class_name(name).unwrap();
+    let mut fields = vec![];
+
+    for node in &class.body {
+        match node {
+            Node::Variable(variable) => {
+                let field_name = &variable.declaration.ident.0;
+                let r#type = match &variable.declaration.r#type {
+                    Some(x) => match compiler.resolve_type(x) {
+                        Ok(x) => x,
+                        Err(_) => continue,
+                    },
+                    None => match &variable.value {
+                        Expr::Term(Term::Number(..)) => compiler.number_type().into(),
+                        Expr::Term(Term::String(..)) => compiler.string_type().into(),
+                        Expr::Term(Term::Boolean(..)) => compiler.context.bool_type().into(),
+                        _ => {
+                            compiler.diagnostics.error(
+                                Code::InvalidClassMember,
+                                &variable.declaration.ident.1,
+                                format!("Field `{}` of `{}` needs a type", field_name, name),
+                            );
+                            continue;
+                        }
+                    },
+                };
+                let class = variable
+                    .declaration
+                    .r#type
+                    .as_ref()
+                    .and_then(|x| compiler.class_name(&x.ident.0));
+
+                fields.push(Field {
+                    name: field_name.clone(),
+                    r#type,
+                    class,
+                    public: variable
+                        .modifiers
+                        .iter()
+                        .any(|x| matches!(x, VariableMod::Public)),
+                });
+            }
+            Node::Function(function) => {
+                let method = method_name(&llvm_name, &function.declaration.ident.0);
+                let _ = declare_function_as(compiler, &method, function, true);
+            }
+            _ => {
+                compiler.diagnostics.error(
+                    Code::InvalidClassMember,
+                    node.span(),
+                    format!("Class `{}` can only contain fields and methods", name),
+                );
+            }
+        }
+    }
+
+    let mut classes = compiler.classes.borrow_mut();
+    let info = classes.get_mut(&llvm_name).unwrap();
+    let field_types: Vec<BasicTypeEnum> = fields.iter().map(|x| x.r#type).collect();
+    info.struct_type.set_body(&field_types, false);
+    info.fields = fields;
+}
+
+impl Compile for Class {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let name = compiler.class_name(&self.ident.0).unwrap();
+        // A second class with the same name, the error was reported when it was declared
+        if compiler.classes.borrow()[&name].span != self.ident.1 {
+            return Ok(());
+        }
+        build_initializer(compiler, self, &name);
+
+        for node in &self.body {
+            if let Node::Function(function) = node {
+                let method = method_name(&name, &function.declaration.ident.0);
+                // Methods with errors in their signature are not declared
+                if let Some(value) = compiler.module.get_function(&method) {
+                    compile_function_body(compiler, value, function, Some(&name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds `Class.<init>`, which sets every field to its initial value. `name` is the LLVM name of
+/// the class.
+fn build_initializer(compiler: &Compiler, class: &Class, name: &str) {
+    let init_type = compiler
+        .context
+        .void_type()
+        .fn_type(&[compiler.string_type().into()], false);
+    let init = compiler
+        .module
+        .add_function(&method_name(name, INITIALIZER), init_type, None);
+
+    let previous_block = compiler.builder.get_insert_block();
+    let entry = compiler.context.append_basic_block(init, "entry");
+    compiler.builder.position_at_end(entry);
+    compiler.debug_enter_function(init, "init", &class.span.file, class.span.line);
+    let outer_scopes = compiler.symbols.borrow_mut().enter_function();
+    let outer_loops = compiler.loops.take();
+    let outer_catches = compiler.catches.take();
+
+    let object = init.get_first_param().unwrap().into_pointer_value();
+    let struct_type = compiler.classes.borrow()[name].struct_type;
+    let variables = class.body.iter().filter_map(|x| match x {
+        Node::Variable(x) => Some(x),
+        _ => None,
+    });
+
+    for variable in variables {
+        compiler.debug_location(&variable.span);
+        let field_name = &variable.declaration.ident.0;
+        // Fields with errors are left out of the class
+        let (index, r#type) = match compiler.classes.borrow()[name]
+            .fields
+            .iter()
+            .enumerate()
+            .find(|x| &x.1.name == field_name)
+        {
+            Some((index, field)) => (index, field.r#type),
+            None => continue,
+        };
+        let value = match variable.value {
+            Expr::Null(_) => zero_value(r#type),
+            _ => match variable.value.compile_value(compiler) {
+                Ok(x) => x,
+                Err(_) => continue,
+            },
+        };
+        if value.get_type() != r#type {
+            compiler.diagnostics.error(
+                Code::TypeMismatch,
+                variable.value.span(),
+                format!(
+                    "Field `{}` of `{}` is assigned a value of the wrong type",
+                    field_name, class.ident.0
+                ),
+            );
+            continue;
+        }
+
+        let pointer = compiler
+            .builder
+            .build_struct_gep(struct_type, object, index as u32, "field")
+            .unwrap();
+        compiler.builder.build_store(pointer, value);
+    }
+    compiler.builder.build_return(None);
+
+    compiler.symbols.borrow_mut().leave_function(outer_scopes);
+    compiler.loops.replace(outer_loops);
+    compiler.catches.replace(outer_catches);
+    compiler.debug_leave_scope();
+    if let Some(x) = previous_block {
+        compiler.builder.position_at_end(x);
+    }
+}
+
+impl<'ctx> Compiler<'ctx> {
+    /// True if a class called `name` is visible from the current file
+    pub fn is_class(&self, name: &str) -> bool {
+        self.class_names.borrow().contains_key(name)
+    }
+
+    /// The LLVM name of the class called `name` in the current file
+    pub fn class_name(&self, name: &str) -> Option<String> {
+        self.class_names.borrow().get(name).cloned()
+    }
+
+    /// The class of the object a term evaluates to, if it is known while compiling
+    pub fn class_of(&self, term: &Term) -> Option<String> {
+        match term {
+            Term::Call(x) if x.receiver.is_none() => self.class_name(&x.ident.0),
+            Term::Ident(x) => self.symbols.borrow().get(&x.0).and_then(|x| x.class),
+            Term::Expr(x) => match x.as_ref() {
+                Expr::Term(x) => self.class_of(x),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Makes `self` and the fields of a class visible inside one of its methods
+    pub fn bind_self(&self, class: &str, object: PointerValue<'ctx>) {
+        let classes = self.classes.borrow();
+        let info = &classes[class];
+        let mut symbols = self.symbols.borrow_mut();
+
+        let r#type = self.string_type().into();
+        let pointer = self.build_entry_alloca(r#type, "self");
+        self.builder.build_store(pointer, object);
+        symbols.insert(
+            "self",
+            Symbol {
+                pointer,
+                r#type,
+                class: Some(class.to_string()),
+            },
+        );
+
+        for (index, field) in info.fields.iter().enumerate() {
+            let pointer = self
+                .builder
+                .build_struct_gep(info.struct_type, object, index as u32, &field.name)
+                .unwrap();
+            symbols.insert(
+                &field.name,
+                Symbol {
+                    pointer,
+                    r#type: field.r#type,
+                    class: field.class.clone(),
+                },
+            );
+        }
+    }
+
+    /// Allocates an object, initializes its fields and runs `cooK` with `args`
+    pub fn build_construct(
+        &self,
+        class: &str,
+        args: &[BasicValueEnum<'ctx>],
+        call: &Call,
+    ) -> Result<PointerValue<'ctx>, Reported> {
+        let struct_type = self.classes.borrow()[class].struct_type;
+        let object = self.builder.build_malloc(struct_type, "object").unwrap();
+
+        let init = self
+            .module
+            .get_function(&method_name(class, INITIALIZER))
+            .unwrap();
+        self.builder.build_call(init, &[object.into()], "init");
+
+        match self.module.get_function(&method_name(class, "cooK")) {
+            Some(constructor) => {
+                self.build_method_call(constructor, object, args, call)?;
+            }
+            None if !args.is_empty() => {
+                return Err(self.diagnostics.error(
+                    Code::ArgumentCount,
+                    &call.span,
+                    format!("Class `{}` has no `cooK` that takes arguments", class),
+                ))
+            }
+            None => {}
+        }
+
+        Ok(object)
+    }
+
+    /// Calls a method with `object` as `self`
+    pub fn build_method_call(
+        &self,
+        method: FunctionValue<'ctx>,
+        object: PointerValue<'ctx>,
+        args: &[BasicValueEnum<'ctx>],
+        call: &Call,
+    ) -> Result<Option<BasicValueEnum<'ctx>>, Reported> {
+        check_args(self, method, true, args, call)?;
+
+        let mut all_args: Vec<BasicMetadataValueEnum> = vec![object.into()];
+        all_args.extend(args.iter().map(|x| BasicMetadataValueEnum::from(*x)));
+
+        let value = self
+            .builder
+            .build_call(method, &all_args, "call")
+            .try_as_basic_value()
+            .left();
+        self.build_bullet_check();
+        Ok(value)
+    }
+
+    /// Loads a field of an object, private fields are only visible inside of the class
+    pub fn build_field_load(
+        &self,
+        term: &Term,
+        field: &str,
+        span: &Span,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        let class = match self.class_of(term) {
+            Some(x) => x,
+            None => {
+                return Err(self.diagnostics.error(
+                    Code::TypeMismatch,
+                    term.span(),
+                    format!("Only objects have fields, `{}` can't be accessed", field),
+                ))
+            }
+        };
+        let object = term.compile_value(self)?.into_pointer_value();
+
+        let classes = self.classes.borrow();
+        let info = &classes[&class];
+        let (index, info_field) = match info.fields.iter().enumerate().find(|x| x.1.name == field) {
+            Some(x) => x,
+            None => {
+                return Err(self.diagnostics.error(
+                    Code::Undefined,
+                    span,
+                    format!("Class `{}` has no field `{}`", class, field),
+                ))
+            }
+        };
+
+        let inside_class = self.current_class.borrow().as_deref() == Some(class.as_str());
+        if !info_field.public && !inside_class {
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Code::InaccessibleField,
+                    span,
+                    format!("Field `{}` of `{}` is private", field, class),
+                )
+                .with_help(format!("make it public with `bar meth {}`", field)),
+            );
+        }
+
+        let pointer = self
+            .builder
+            .build_struct_gep(info.struct_type, object, index as u32, field)
+            .unwrap();
+        Ok(self.builder.build_load(info_field.r#type, pointer, field))
+    }
+
+    /// Runs `snoRt` and frees the object owned by `symbol`. Nothing happens if the variable holds
+    /// `wat` or the object is `keep`, which is being returned.
+    pub fn build_destroy(&self, symbol: &Symbol<'ctx>, keep: Option<PointerValue<'ctx>>) {
+        let class = symbol.class.as_deref().unwrap();
+        let function = self.current_function();
+        let destroy = self.context.append_basic_block(function, "destroy");
+        let after = self.context.append_basic_block(function, "destroyed");
+
+        let object = self
+            .builder
+            .build_load(symbol.r#type, symbol.pointer, "object")
+            .into_pointer_value();
+        let mut skip = self.builder.build_is_null(object, "is_null");
+        if let Some(keep) = keep {
+            let int_type = self.context.i64_type();
+            let is_kept = self.builder.build_int_compare(
+                IntPredicate::EQ,
+                self.builder.build_ptr_to_int(object, int_type, "object"),
+                self.builder.build_ptr_to_int(keep, int_type, "keep"),
+                "is_kept",
+            );
+            skip = self.builder.build_or(skip, is_kept, "skip");
+        }
+        self.builder.build_conditional_branch(skip, after, destroy);
+
+        self.builder.position_at_end(destroy);
+        if let Some(destructor) = self.module.get_function(&method_name(class, "snoRt")) {
+            self.builder
+                .build_call(destructor, &[object.into()], "snoRt");
+        }
+        self.builder.build_free(object);
+        self.builder.build_unconditional_branch(after);
+
+        self.builder.position_at_end(after);
+    }
+
+    /// Destroys the objects owned by block scopes deeper than `depth`, and by the globals too if
+    /// `globals` is set
+    pub fn build_destroy_objects(
+        &self,
+        depth: usize,
+        globals: bool,
+        keep: Option<PointerValue<'ctx>>,
+    ) {
+        let mut objects = self.symbols.borrow().objects_from(depth);
+        if globals {
+            objects.extend(self.symbols.borrow().global_objects());
+        }
+
+        for object in &objects {
+            self.build_destroy(object, keep);
+        }
+    }
+}
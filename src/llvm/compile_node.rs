@@ -1,48 +1,1042 @@
-use inkwell::values::BasicMetadataValueEnum;
+use inkwell::{
+    types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum},
+    values::{BasicMetadataValueEnum, BasicValueEnum, FunctionValue, IntValue, PointerValue},
+    FloatPredicate, IntPredicate,
+};
 
-use crate::parser::{Call, Term};
+use crate::{
+    errors::{Code, Diagnostic, Reported},
+    parser::{
+        Assignment, BinaryExpr, Break, Call, Catch, ConditionalExpr, ConditionalOperator, Else,
+        ElseIf, Expr, Foolean, Function, FunctionMod, If, IfBlock, IfNode, Index, IndexExpr, Loop,
+        MathOperator, Return, Span, Term, Throw, Try, TryCatch, Variable,
+    },
+};
 
-use super::Compiler;
+use super::{classes::method_name, llvm, llvm_block, symbols::Symbol, zero_value, Compiler};
 
+/// Statements are compiled one at a time. An error is added to the diagnostics and stops the
+/// statement, the next one is compiled anyway.
 pub trait Compile {
-    fn compile(&self, compiler: &Compiler);
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported>;
+}
+
+/// Nodes that produce a value when compiled
+pub trait CompileValue {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported>;
+}
+
+/// Checks the arguments of `call` against the parameters of `function`. Methods take their object
+/// as the first parameter, it is not one of the arguments.
+pub fn check_args<'ctx>(
+    compiler: &Compiler<'ctx>,
+    function: FunctionValue<'ctx>,
+    is_method: bool,
+    args: &[BasicValueEnum<'ctx>],
+    call: &Call,
+) -> Result<(), Reported> {
+    let (kind, name) = if is_method {
+        ("Method", function.get_name().to_str().unwrap())
+    } else {
+        ("Function", call.ident.0.as_str())
+    };
+    let params: Vec<_> = function
+        .get_param_iter()
+        .skip(is_method as usize)
+        .map(|x| x.get_type())
+        .collect();
+
+    if params.len() != args.len() {
+        return Err(compiler.diagnostics.error(
+            Code::ArgumentCount,
+            &call.span,
+            format!(
+                "{} `{}` takes {} arguments but {} were given",
+                kind,
+                name,
+                params.len(),
+                args.len()
+            ),
+        ));
+    }
+
+    for (index, (arg, param)) in args.iter().zip(&params).enumerate() {
+        if arg.get_type() != *param {
+            return Err(compiler.diagnostics.error(
+                Code::TypeMismatch,
+                call.args[index].span(),
+                format!(
+                    "Argument {} of `{}` is a value of the wrong type",
+                    index + 1,
+                    name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn build_call<'ctx>(
+    call: &Call,
+    compiler: &Compiler<'ctx>,
+) -> Result<Option<BasicValueEnum<'ctx>>, Reported> {
+    let name = call.ident.0.as_str();
+    let args = call
+        .args
+        .iter()
+        .map(|x| x.compile_value(compiler))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(receiver) = &call.receiver {
+        let symbol = match compiler.symbols.borrow().get(receiver.0.as_str()) {
+            Some(x) => x,
+            None => {
+                return Err(compiler.diagnostics.error(
+                    Code::UndefinedVariable,
+                    &receiver.1,
+                    format!("Variable `{}` not defined", receiver.0),
+                ))
+            }
+        };
+        let class = match &symbol.class {
+            Some(x) => x,
+            None => {
+                return Err(compiler.diagnostics.error(
+                    Code::Undefined,
+                    &receiver.1,
+                    format!("`{}` is not an object, it has no methods", receiver.0),
+                ))
+            }
+        };
+        let method = match compiler.module.get_function(&method_name(class, name)) {
+            Some(x) => x,
+            None => {
+                return Err(compiler.diagnostics.error(
+                    Code::Undefined,
+                    &call.ident.1,
+                    format!("Class `{}` has no method `{}`", class, name),
+                ))
+            }
+        };
+        let object = compiler
+            .builder
+            .build_load(symbol.r#type, symbol.pointer, receiver.0.as_str())
+            .into_pointer_value();
+
+        return compiler.build_method_call(method, object, &args, call);
+    }
+
+    if let Some(class) = compiler.class_name(name) {
+        return Ok(Some(compiler.build_construct(&class, &args, call)?.into()));
+    }
+
+    // Inside of a class, methods of the same class can be called without `self.`
+    let current_class = compiler.current_class.borrow().clone();
+    if let Some(class) = current_class {
+        if let Some(method) = compiler.module.get_function(&method_name(&class, name)) {
+            let symbol = compiler.symbols.borrow().get("self").unwrap();
+            let object = compiler
+                .builder
+                .build_load(symbol.r#type, symbol.pointer, "self")
+                .into_pointer_value();
+
+            return compiler.build_method_call(method, object, &args, call);
+        }
+    }
+
+    let function = match compiler.get_function(name) {
+        Some(x) => x,
+        None if compiler.nostd && compiler.std_functions.contains(name) => {
+            return Err(compiler.diagnostics.error(
+                Code::Nostd,
+                &call.ident.1,
+                format!(
+                    "`{}` is part of the standard library, which is not available in nostd builds",
+                    name
+                ),
+            ))
+        }
+        None => {
+            return Err(compiler.diagnostics.error(
+                Code::Undefined,
+                &call.ident.1,
+                format!("Function `{}` not defined", name),
+            ))
+        }
+    };
+
+    if function.is_null() || function.is_undef() {
+        return Err(compiler.diagnostics.error(
+            Code::Undefined,
+            &call.ident.1,
+            format!("Function `{}` is null or undefined", name),
+        ));
+    }
+
+    check_args(compiler, function, false, &args, call)?;
+
+    let args: Vec<BasicMetadataValueEnum> = args.into_iter().map(|x| x.into()).collect();
+    let value = compiler
+        .builder
+        .build_call(function, &args, "call")
+        .try_as_basic_value()
+        .left();
+
+    let llvm_name = function.get_name().to_str().unwrap();
+    if compiler.user_functions.borrow().contains(llvm_name) {
+        compiler.build_bullet_check();
+    }
+
+    Ok(value)
 }
 
 impl Compile for Call {
-    fn compile(&self, compiler: &Compiler) {
-        let function = match compiler.module.get_function(self.ident.0.as_str()) {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        build_call(self, compiler).map(|_| ())
+    }
+}
+
+/// Adds the signature of a function to the module, its body is compiled later
+pub fn declare_function<'ctx>(
+    compiler: &Compiler<'ctx>,
+    function: &Function,
+) -> Result<FunctionValue<'ctx>, Reported> {
+    let name = &function.declaration.ident.0;
+    let llvm_name = compiler.mangle(name);
+    compiler
+        .functions
+        .borrow_mut()
+        .insert(name.clone(), llvm_name.clone());
+
+    declare_function_as(compiler, &llvm_name, function, false)
+}
+
+/// Declares a function under `name`. Methods take their object as an extra first argument.
+pub fn declare_function_as<'ctx>(
+    compiler: &Compiler<'ctx>,
+    name: &str,
+    function: &Function,
+    is_method: bool,
+) -> Result<FunctionValue<'ctx>, Reported> {
+    if compiler.module.get_function(name).is_some() {
+        return Err(compiler.diagnostics.error(
+            Code::DuplicateDefinition,
+            &function.declaration.ident.1,
+            format!("Function `{}` is already defined", name),
+        ));
+    }
+
+    let mut args = function
+        .args
+        .iter()
+        .map(|x| match &x.r#type {
+            Some(x) => compiler.resolve_type(x).map(|x| x.into()),
+            None => Ok(compiler.number_type().into()),
+        })
+        .collect::<Result<Vec<BasicMetadataTypeEnum>, _>>()?;
+
+    if is_method {
+        args.insert(0, compiler.string_type().into());
+    }
+
+    let function_type = match &function.declaration.r#type {
+        Some(x) => compiler.resolve_type(x)?.fn_type(&args, false),
+        None => compiler.context.void_type().fn_type(&args, false),
+    };
+
+    compiler
+        .user_functions
+        .borrow_mut()
+        .insert(name.to_string());
+    Ok(compiler.module.add_function(name, function_type, None))
+}
+
+/// Compiles the body of a declared function, or of a method if `class` is set
+pub fn compile_function_body(
+    compiler: &Compiler,
+    value: FunctionValue,
+    function: &Function,
+    class: Option<&str>,
+) {
+    // A second function with the same name, the error was reported when it was declared
+    if value.count_basic_blocks() > 0 {
+        return;
+    }
+
+    let previous_block = compiler.builder.get_insert_block();
+    let entry = compiler.context.append_basic_block(value, "entry");
+    compiler.builder.position_at_end(entry);
+    compiler.debug_enter_function(
+        value,
+        &function.declaration.ident.0,
+        &function.span.file,
+        function.span.line,
+    );
+
+    // A function can't see the locals, loops or walls of the code around its definition
+    let outer_scopes = compiler.symbols.borrow_mut().enter_function();
+    let outer_loops = compiler.loops.take();
+    let outer_catches = compiler.catches.take();
+    let outer_class = compiler.current_class.replace(class.map(|x| x.to_string()));
+    let is_debug = function
+        .modifiers
+        .iter()
+        .any(|x| matches!(x, FunctionMod::Debug));
+    let outer_trace = compiler.trace.replace(is_debug && !compiler.release);
+
+    compiler.symbols.borrow_mut().push_scope();
+
+    let mut params = value.get_param_iter();
+    if let Some(class) = class {
+        let object = params.next().unwrap().into_pointer_value();
+        compiler.bind_self(class, object);
+    }
+
+    // Methods have their object as the first parameter
+    let first_arg = if class.is_some() { 2 } else { 1 };
+    for (index, (arg, param)) in function.args.iter().zip(&params).enumerate() {
+        let name = arg.ident.0.as_str();
+        let r#type = param.get_type();
+        let pointer = compiler.build_entry_alloca(r#type, name);
+        let class = arg
+            .r#type
+            .as_ref()
+            .and_then(|x| compiler.class_name(&x.ident.0));
+
+        compiler.builder.build_store(pointer, param);
+        compiler.debug_declare_variable(
+            name,
+            pointer,
+            r#type,
+            &arg.ident.1,
+            Some(first_arg + index as u32),
+        );
+        compiler.symbols.borrow_mut().insert(
+            name,
+            Symbol {
+                pointer,
+                r#type,
+                class,
+            },
+        );
+    }
+
+    llvm(compiler, &function.body);
+
+    // Falling off the end returns `wat`
+    if compiler
+        .builder
+        .get_insert_block()
+        .and_then(|x| x.get_terminator())
+        .is_none()
+    {
+        compiler.build_destroy_objects(0, false, None);
+        match value.get_type().get_return_type() {
+            Some(x) => compiler.builder.build_return(Some(&zero_value(x))),
+            None => compiler.builder.build_return(None),
+        };
+    }
+
+    compiler.symbols.borrow_mut().leave_function(outer_scopes);
+    compiler.loops.replace(outer_loops);
+    compiler.catches.replace(outer_catches);
+    compiler.current_class.replace(outer_class);
+    compiler.trace.replace(outer_trace);
+
+    compiler.debug_leave_scope();
+    if let Some(x) = previous_block {
+        compiler.builder.position_at_end(x);
+    }
+}
+
+impl Compile for Function {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let function = match compiler.get_function(&self.declaration.ident.0) {
             Some(x) => x,
-            None => panic!("Function `{}` not defined", self.ident.0),
+            None => declare_function(compiler, self)?,
+        };
+
+        compile_function_body(compiler, function, self, None);
+        Ok(())
+    }
+}
+
+impl Compile for Return {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let function = compiler.current_function();
+        let name = function.get_name().to_str().unwrap();
+
+        match function.get_type().get_return_type() {
+            Some(r#type) => {
+                let value = match self.value {
+                    Expr::Null(_) => zero_value(r#type),
+                    _ => self.value.compile_value(compiler)?,
+                };
+
+                let value = match (value, r#type) {
+                    (x, y) if x.get_type() == y => x,
+                    // Booleans are zero extended, so `Yup` is returned as 1
+                    (BasicValueEnum::IntValue(x), BasicTypeEnum::IntType(y))
+                        if x.get_type().get_bit_width() < y.get_bit_width() =>
+                    {
+                        compiler.build_int_widen(x, y).into()
+                    }
+                    (BasicValueEnum::IntValue(x), BasicTypeEnum::IntType(y)) => {
+                        compiler.builder.build_int_truncate(x, y, "ret_cast").into()
+                    }
+                    _ => {
+                        return Err(compiler.diagnostics.error(
+                            Code::TypeMismatch,
+                            self.value.span(),
+                            format!("Function `{}` returns a value of the wrong type", name),
+                        ))
+                    }
+                };
+
+                let keep = match value {
+                    BasicValueEnum::PointerValue(x) => Some(x),
+                    _ => None,
+                };
+                compiler.build_destroy_objects(0, name == "main", keep);
+                compiler.builder.build_return(Some(&value));
+            }
+            None => {
+                if !matches!(self.value, Expr::Null(_)) {
+                    return Err(compiler.diagnostics.error(
+                        Code::TypeMismatch,
+                        self.value.span(),
+                        format!("Function `{}` has no return type but returns a value", name),
+                    ));
+                }
+                compiler.build_destroy_objects(0, false, None);
+                compiler.builder.build_return(None);
+            }
         };
 
-        if function.is_null() || function.is_undef() {
-            panic!("Function `{}` is null or undefined", self.ident.0);
+        compiler.build_dead_block("after_return");
+        Ok(())
+    }
+}
+
+impl Compile for Variable {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let name = self.declaration.ident.0.as_str();
+        let declared_type = match &self.declaration.r#type {
+            Some(x) => Some(compiler.resolve_type(x)?),
+            None => None,
+        };
+
+        let class = match &self.declaration.r#type {
+            Some(x) => compiler.class_name(&x.ident.0),
+            None => match &self.value {
+                Expr::Term(x) => compiler.class_of(x),
+                _ => None,
+            },
+        };
+        // Variables that are assigned a new object own it
+        let is_owner = match &self.value {
+            Expr::Term(Term::Call(x)) => x.receiver.is_none() && compiler.is_class(&x.ident.0),
+            _ => false,
+        };
+
+        let value = match (&self.value, declared_type) {
+            (Expr::Null(_), Some(x)) => zero_value(x),
+            _ => self.value.compile_value(compiler)?,
+        };
+        let r#type = declared_type.unwrap_or(value.get_type());
+
+        if value.get_type() != r#type {
+            let mut diagnostic = Diagnostic::new(
+                Code::TypeMismatch,
+                self.value.span(),
+                format!("Variable `{}` is assigned a value of the wrong type", name),
+            );
+            if let Some(x) = &self.declaration.r#type {
+                diagnostic = diagnostic.with_secondary(&x.ident.1, "type declared here");
+            }
+            return Err(compiler.diagnostics.push(diagnostic));
         }
 
-        let args: Vec<BasicMetadataValueEnum> = self
-            .args
-            .iter()
-            .map(|x| match x {
-                Term::Number(x) => compiler
-                    .context
-                    .i128_type()
-                    .const_int((*x).try_into().unwrap(), false)
-                    .into(),
-                Term::String(x) => compiler
-                    .builder
-                    .build_global_string_ptr(x, ".str")
-                    .as_pointer_value()
-                    .into(),
-                Term::Ident(_) => {
-                    // TODO: Variables, somehow
-                    // compiler.builder
-                    todo!()
+        let span = &self.declaration.ident.1;
+        let pointer = if compiler.symbols.borrow().is_top_level() {
+            let global = compiler
+                .module
+                .add_global(r#type, None, &compiler.mangle(name));
+            global.set_initializer(&zero_value(r#type));
+            compiler.debug_declare_global(name, global, r#type, span);
+            global.as_pointer_value()
+        } else {
+            let pointer = compiler.build_entry_alloca(r#type, name);
+            compiler.debug_declare_variable(name, pointer, r#type, span, None);
+            pointer
+        };
+
+        compiler.builder.build_store(pointer, value);
+        compiler.build_trace(name, value, class.as_deref(), &self.span);
+
+        let symbol = Symbol {
+            pointer,
+            r#type,
+            class,
+        };
+        let mut symbols = compiler.symbols.borrow_mut();
+        if is_owner {
+            symbols.insert_object(symbol.clone());
+        }
+        symbols.insert(name, symbol);
+        Ok(())
+    }
+}
+
+impl Compile for Assignment {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let symbol = match compiler.symbols.borrow().get(self.ident.0.as_str()) {
+            Some(x) => x,
+            None => {
+                return Err(compiler.diagnostics.error(
+                    Code::UndefinedVariable,
+                    &self.ident.1,
+                    format!("Variable `{}` not defined", self.ident.0),
+                ))
+            }
+        };
+
+        // Like in `Variable`, assigning a new object makes the variable its owner
+        let is_owner = match &self.value {
+            Expr::Term(Term::Call(x)) => x.receiver.is_none() && compiler.is_class(&x.ident.0),
+            _ => false,
+        };
+
+        let value = match self.value {
+            Expr::Null(_) => zero_value(symbol.r#type),
+            _ => self.value.compile_value(compiler)?,
+        };
+
+        if value.get_type() != symbol.r#type {
+            return Err(compiler.diagnostics.error(
+                Code::TypeMismatch,
+                self.value.span(),
+                format!(
+                    "Variable `{}` is assigned a value of the wrong type",
+                    self.ident.0
+                ),
+            ));
+        }
+
+        // The object the variable owned until now is replaced, so it's destroyed first
+        let was_owner = compiler.symbols.borrow().is_object(&self.ident.0);
+        if was_owner {
+            compiler.build_destroy(&symbol, None);
+        }
+
+        compiler.builder.build_store(symbol.pointer, value);
+        compiler.build_trace(&self.ident.0, value, symbol.class.as_deref(), &self.span);
+
+        compiler
+            .symbols
+            .borrow_mut()
+            .set_object(&self.ident.0, is_owner && symbol.class.is_some());
+        Ok(())
+    }
+}
+
+impl Compile for Loop {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let function = compiler.current_function();
+        let body = compiler.context.append_basic_block(function, "loop");
+        let exit = compiler.context.append_basic_block(function, "loop_exit");
+
+        compiler.builder.build_unconditional_branch(body);
+        compiler.builder.position_at_end(body);
+
+        let depth = compiler.symbols.borrow().depth();
+        compiler.loops.borrow_mut().push((exit, depth));
+        llvm_block(compiler, &self.body);
+        compiler.loops.borrow_mut().pop();
+
+        compiler.build_branch_if_open(body);
+        compiler.builder.position_at_end(exit);
+        Ok(())
+    }
+}
+
+impl Compile for Break {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        // `sthu` outside of a loop was already reported by `check_breaks` while parsing
+        let (exit, depth) = match compiler.loops.borrow().last() {
+            Some(x) => *x,
+            None => return Err(Reported),
+        };
+        compiler.build_destroy_objects(depth, false, None);
+        compiler.builder.build_unconditional_branch(exit);
+        compiler.build_dead_block("after_break");
+        Ok(())
+    }
+}
+
+impl Compile for IfBlock {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let function = compiler.current_function();
+        let merge = compiler.context.append_basic_block(function, "if_merge");
+
+        for if_node in &self.if_nodes {
+            match if_node {
+                IfNode::If(If { expr, body }) | IfNode::ElseIf(ElseIf { expr, body }) => {
+                    let condition =
+                        compiler.build_truthy(expr.compile_value(compiler)?, expr.span())?;
+                    let then = compiler.context.append_basic_block(function, "if_then");
+                    let next = compiler.context.append_basic_block(function, "if_next");
+
+                    compiler
+                        .builder
+                        .build_conditional_branch(condition, then, next);
+
+                    compiler.builder.position_at_end(then);
+                    llvm_block(compiler, body);
+                    compiler.build_branch_if_open(merge);
+
+                    compiler.builder.position_at_end(next);
+                }
+                IfNode::Else(Else { body }) => {
+                    llvm_block(compiler, body);
                 }
-            })
-            .collect();
+            }
+        }
+
+        compiler.build_branch_if_open(merge);
+        compiler.builder.position_at_end(merge);
+        Ok(())
+    }
+}
+
+impl Compile for Throw {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let bullet = match self.value.compile_value(compiler)? {
+            BasicValueEnum::PointerValue(x) => x,
+            _ => {
+                return Err(compiler.diagnostics.error(
+                    Code::TypeMismatch,
+                    self.value.span(),
+                    "Only strings can be shot",
+                ))
+            }
+        };
 
         compiler
             .builder
-            .build_call(function, args.as_slice(), "call");
+            .build_store(compiler.bullet_global(), bullet);
+        compiler.build_store_bullet_origin(&self.span);
+        compiler.build_unwind();
+        compiler.build_dead_block("after_shoot");
+        Ok(())
+    }
+}
+
+impl Compile for TryCatch {
+    fn compile(&self, compiler: &Compiler) -> Result<(), Reported> {
+        let TryCatch {
+            r#try: Try(try_body),
+            catch: Catch(ident, catch_body),
+            ..
+        } = self;
+
+        let function = compiler.current_function();
+        let wall = compiler.context.append_basic_block(function, "wall");
+        let merge = compiler.context.append_basic_block(function, "test_end");
+
+        compiler.catches.borrow_mut().push(wall);
+        llvm_block(compiler, try_body);
+        compiler.catches.borrow_mut().pop();
+        compiler.build_branch_if_open(merge);
+
+        compiler.builder.position_at_end(wall);
+        compiler.symbols.borrow_mut().push_scope();
+
+        if let Some(ident) = ident {
+            let name = ident.0.as_str();
+            let r#type = compiler.string_type().into();
+            let pointer = compiler.build_entry_alloca(r#type, name);
+
+            compiler
+                .builder
+                .build_store(pointer, compiler.build_load_bullet());
+            compiler.symbols.borrow_mut().insert(
+                name,
+                Symbol {
+                    pointer,
+                    r#type,
+                    class: None,
+                },
+            );
+        }
+
+        // The bullet has been caught, stop it from unwinding any further
+        compiler.builder.build_store(
+            compiler.bullet_global(),
+            compiler.string_type().const_null(),
+        );
+
+        llvm(compiler, catch_body);
+        compiler.symbols.borrow_mut().pop_scope();
+
+        compiler.build_branch_if_open(merge);
+        compiler.builder.position_at_end(merge);
+        Ok(())
+    }
+}
+
+/// Compares the characters of two strings with libc's `strcmp`. `wat` is only equal to itself.
+fn build_string_equality<'ctx>(
+    compiler: &Compiler<'ctx>,
+    lhs: PointerValue<'ctx>,
+    rhs: PointerValue<'ctx>,
+) -> IntValue<'ctx> {
+    let builder = &compiler.builder;
+    let function = compiler.current_function();
+    let entry = builder.get_insert_block().unwrap();
+    let compare = compiler.context.append_basic_block(function, "strcmp");
+    let done = compiler.context.append_basic_block(function, "strcmp_done");
+
+    let address = compiler.context.i64_type();
+    let same = builder.build_int_compare(
+        IntPredicate::EQ,
+        builder.build_ptr_to_int(lhs, address, "lhs"),
+        builder.build_ptr_to_int(rhs, address, "rhs"),
+        "same",
+    );
+    let either_null = builder.build_or(
+        builder.build_is_null(lhs, "lhs_null"),
+        builder.build_is_null(rhs, "rhs_null"),
+        "either_null",
+    );
+    let decided = builder.build_or(same, either_null, "decided");
+    builder.build_conditional_branch(decided, done, compare);
+
+    builder.position_at_end(compare);
+    let strcmp = compiler.module.get_function("strcmp").unwrap_or_else(|| {
+        let r#type = compiler.context.i32_type().fn_type(
+            &[compiler.string_type().into(), compiler.string_type().into()],
+            false,
+        );
+        compiler.module.add_function("strcmp", r#type, None)
+    });
+    let order = builder
+        .build_call(strcmp, &[lhs.into(), rhs.into()], "strcmp")
+        .try_as_basic_value()
+        .left()
+        .unwrap()
+        .into_int_value();
+    let equal = builder.build_int_compare(
+        IntPredicate::EQ,
+        order,
+        compiler.context.i32_type().const_zero(),
+        "equal",
+    );
+    builder.build_unconditional_branch(done);
+
+    builder.position_at_end(done);
+    let result = builder.build_phi(compiler.context.bool_type(), "string_eq");
+    result.add_incoming(&[(&same, entry), (&equal, compare)]);
+    result.as_basic_value().into_int_value()
+}
+
+/// Compares two values. Strings are equal if their characters are, objects only if they are the
+/// same object.
+fn build_comparison<'ctx>(
+    compiler: &Compiler<'ctx>,
+    operator: &ConditionalOperator,
+    lhs: BasicValueEnum<'ctx>,
+    rhs: BasicValueEnum<'ctx>,
+    objects: bool,
+    span: &Span,
+) -> Result<IntValue<'ctx>, Reported> {
+    let (int_predicate, float_predicate) = match operator {
+        ConditionalOperator::Equality => (IntPredicate::EQ, FloatPredicate::OEQ),
+        ConditionalOperator::AntiEquality => (IntPredicate::NE, FloatPredicate::ONE),
+    };
+
+    Ok(match (lhs, rhs) {
+        (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+            let r#type = if lhs.get_type().get_bit_width() >= rhs.get_type().get_bit_width() {
+                lhs.get_type()
+            } else {
+                rhs.get_type()
+            };
+            let lhs = compiler.build_int_widen(lhs, r#type);
+            let rhs = compiler.build_int_widen(rhs, r#type);
+
+            compiler
+                .builder
+                .build_int_compare(int_predicate, lhs, rhs, "cmp")
+        }
+        (BasicValueEnum::FloatValue(lhs), BasicValueEnum::FloatValue(rhs)) => compiler
+            .builder
+            .build_float_compare(float_predicate, lhs, rhs, "cmp"),
+        (BasicValueEnum::PointerValue(lhs), BasicValueEnum::PointerValue(rhs)) if !objects => {
+            let equal = build_string_equality(compiler, lhs, rhs);
+            match operator {
+                ConditionalOperator::Equality => equal,
+                ConditionalOperator::AntiEquality => compiler.builder.build_not(equal, "ne"),
+            }
+        }
+        (BasicValueEnum::PointerValue(lhs), BasicValueEnum::PointerValue(rhs)) => {
+            let r#type = compiler.context.i64_type();
+            let lhs = compiler.builder.build_ptr_to_int(lhs, r#type, "lhs");
+            let rhs = compiler.builder.build_ptr_to_int(rhs, r#type, "rhs");
+
+            compiler
+                .builder
+                .build_int_compare(int_predicate, lhs, rhs, "cmp")
+        }
+        _ => {
+            return Err(compiler.diagnostics.error(
+                Code::TypeMismatch,
+                span,
+                "Values of different types can't be compared",
+            ))
+        }
+    })
+}
+
+impl CompileValue for ConditionalExpr {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        let operands = self
+            .terms
+            .iter()
+            .map(|x| x.operand.compile_value(compiler))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Chains compare each neighbouring pair, `a ⅀ b ⅀ c` is `a ⅀ b` and `b ⅀ c`
+        let mut result = compiler.context.bool_type().const_all_ones();
+        for (index, operands) in operands.windows(2).enumerate() {
+            let (term, next) = (&self.terms[index], &self.terms[index + 1]);
+            let operator = term.operator.as_ref().unwrap();
+            let objects = compiler.class_of(&term.operand).is_some()
+                || compiler.class_of(&next.operand).is_some();
+            let comparison = build_comparison(
+                compiler,
+                operator,
+                operands[0],
+                operands[1],
+                objects,
+                &self.span,
+            )?;
+            result = compiler.builder.build_and(result, comparison, "and");
+        }
+
+        Ok(result.into())
+    }
+}
+
+fn build_math<'ctx>(
+    compiler: &Compiler<'ctx>,
+    operator: &MathOperator,
+    lhs: BasicValueEnum<'ctx>,
+    rhs: BasicValueEnum<'ctx>,
+    span: &Span,
+) -> Result<BasicValueEnum<'ctx>, Reported> {
+    let builder = &compiler.builder;
+
+    Ok(match (lhs, rhs) {
+        (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+            let r#type = if lhs.get_type().get_bit_width() >= rhs.get_type().get_bit_width() {
+                lhs.get_type()
+            } else {
+                rhs.get_type()
+            };
+            let lhs = compiler.build_int_widen(lhs, r#type);
+            let rhs = compiler.build_int_widen(rhs, r#type);
+
+            let value = match operator {
+                MathOperator::Add => builder.build_int_add(lhs, rhs, "add"),
+                MathOperator::Subtract => builder.build_int_sub(lhs, rhs, "sub"),
+                MathOperator::Multiply => builder.build_int_mul(lhs, rhs, "mul"),
+                MathOperator::Divide => builder.build_int_signed_div(lhs, rhs, "div"),
+                MathOperator::XOR => builder.build_xor(lhs, rhs, "xor"),
+            };
+            value.into()
+        }
+        (BasicValueEnum::FloatValue(lhs), BasicValueEnum::FloatValue(rhs)) => match operator {
+            MathOperator::Add => builder.build_float_add(lhs, rhs, "add"),
+            MathOperator::Subtract => builder.build_float_sub(lhs, rhs, "sub"),
+            MathOperator::Multiply => builder.build_float_mul(lhs, rhs, "mul"),
+            MathOperator::Divide => builder.build_float_div(lhs, rhs, "div"),
+            MathOperator::XOR => {
+                return Err(compiler.diagnostics.error(
+                    Code::InvalidMath,
+                    span,
+                    "Decimals can't be XORed",
+                ))
+            }
+        }
+        .into(),
+        _ => {
+            return Err(compiler.diagnostics.error(
+                Code::InvalidMath,
+                span,
+                "Math is only supported between two numbers of the same kind",
+            ))
+        }
+    })
+}
+
+impl CompileValue for BinaryExpr {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        // There is no precedence, terms are evaluated from left to right
+        let mut terms = self.terms.iter();
+        let first = terms.next().unwrap();
+
+        let mut result = first.operand.compile_value(compiler)?;
+        let mut operator = first.operator.as_ref();
+        for term in terms {
+            let rhs = term.operand.compile_value(compiler)?;
+            result = build_math(compiler, operator.unwrap(), result, rhs, &self.span)?;
+            operator = term.operator.as_ref();
+        }
+
+        Ok(result)
+    }
+}
+
+impl CompileValue for IndexExpr {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        let index = match self.index {
+            Index::Number(x) if x < 1 => {
+                return Err(compiler.diagnostics.error(
+                    Code::IndexOutOfBounds,
+                    &self.span,
+                    format!("Index {} is out of bounds, arrays start at 1", x),
+                ))
+            }
+            Index::Number(x) => x - 1,
+            Index::String(ref x) => return compiler.build_field_load(&self.term, x, &self.span),
+        };
+
+        let value = match self.term.compile_value(compiler)? {
+            BasicValueEnum::PointerValue(x) => x,
+            _ => {
+                return Err(compiler.diagnostics.error(
+                    Code::TypeMismatch,
+                    self.term.span(),
+                    "Only strings and arrays can be indexed",
+                ))
+            }
+        };
+
+        let byte_type = compiler.context.i8_type();
+        let index = compiler.context.i64_type().const_int(index as u64, false);
+        let pointer = unsafe {
+            compiler
+                .builder
+                .build_gep(byte_type, value, &[index], "index")
+        };
+        let byte = compiler
+            .builder
+            .build_load(byte_type, pointer, "byte")
+            .into_int_value();
+
+        Ok(compiler
+            .builder
+            .build_int_z_extend(byte, compiler.number_type(), "char")
+            .into())
+    }
+}
+
+/// `Dunno` and `Huh` are false like `wat`, `Yeet` takes a bit from libc's `rand`
+fn build_foolean<'ctx>(compiler: &Compiler<'ctx>, value: Foolean) -> IntValue<'ctx> {
+    let bool_type = compiler.context.bool_type();
+    match value {
+        Foolean::Yup => bool_type.const_int(1, false),
+        Foolean::Nope | Foolean::Dunno | Foolean::Huh => bool_type.const_zero(),
+        Foolean::Yeet => {
+            let rand = compiler.module.get_function("rand").unwrap_or_else(|| {
+                let r#type = compiler.context.i32_type().fn_type(&[], false);
+                compiler.module.add_function("rand", r#type, None)
+            });
+            let random = compiler
+                .builder
+                .build_call(rand, &[], "rand")
+                .try_as_basic_value()
+                .left()
+                .unwrap()
+                .into_int_value();
+            compiler
+                .builder
+                .build_int_truncate(random, bool_type, "yeet")
+        }
+    }
+}
+
+impl CompileValue for Term {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        Ok(match self {
+            Term::Number(x, _) => compiler
+                .number_type()
+                .const_int_arbitrary_precision(&[*x as u64, (*x >> 64) as u64])
+                .into(),
+            Term::String(x, _) => compiler
+                .builder
+                .build_global_string_ptr(x, ".str")
+                .as_pointer_value()
+                .into(),
+            Term::Boolean(x, _) => build_foolean(compiler, *x).into(),
+            Term::Ident(x) => {
+                let symbol = match compiler.symbols.borrow().get(x.0.as_str()) {
+                    Some(x) => x,
+                    None => {
+                        return Err(compiler.diagnostics.error(
+                            Code::UndefinedVariable,
+                            &x.1,
+                            format!("Variable `{}` not defined", x.0),
+                        ))
+                    }
+                };
+
+                compiler
+                    .builder
+                    .build_load(symbol.r#type, symbol.pointer, x.0.as_str())
+            }
+            Term::Call(x) => match build_call(x, compiler)? {
+                Some(x) => x,
+                None => {
+                    return Err(compiler.diagnostics.error(
+                        Code::NoReturnValue,
+                        &x.span,
+                        format!("Function `{}` does not return a value", x.ident.0),
+                    ))
+                }
+            },
+            Term::Expr(x) => x.compile_value(compiler)?,
+        })
+    }
+}
+
+impl CompileValue for Expr {
+    fn compile_value<'ctx>(
+        &self,
+        compiler: &Compiler<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, Reported> {
+        match self {
+            Expr::Term(x) => x.compile_value(compiler),
+            Expr::Null(_) => Ok(compiler.string_type().const_null().into()),
+            Expr::BinaryExpr(x) => x.compile_value(compiler),
+            Expr::ConditionalExpr(x) => x.compile_value(compiler),
+            Expr::IndexExpr(x) => x.compile_value(compiler),
+        }
     }
 }
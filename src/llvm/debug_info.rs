@@ -0,0 +1,338 @@
+//! DWARF for debug builds, so gdb and lldb can stop at lines of `.rl` files, step through them and
+//! print `meth` variables. Everything here does nothing when `Compiler::debug_info` is None.
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use inkwell::{
+    debug_info::{
+        debug_metadata_version, AsDIScope, DICompileUnit, DIFile, DIFlags, DIFlagsConstants,
+        DILocation, DIScope, DIType, DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder,
+    },
+    module::{FlagBehavior, Module},
+    targets::TargetData,
+    types::BasicTypeEnum,
+    values::{FunctionValue, GlobalValue, PointerValue},
+    AddressSpace,
+};
+
+use crate::parser::Span;
+
+use super::Compiler;
+
+const DW_ATE_BOOLEAN: u32 = 0x02;
+const DW_ATE_SIGNED: u32 = 0x05;
+const DW_ATE_SIGNED_CHAR: u32 = 0x06;
+
+pub struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    unit: DICompileUnit<'ctx>,
+    is_optimized: bool,
+    pointer_bits: u64,
+    /// Scopes being compiled into and the last location set in each, innermost last
+    scopes: RefCell<Vec<(DIScope<'ctx>, DILocation<'ctx>)>>,
+}
+
+/// Debuggers are given absolute paths, so they find the files from any directory
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl<'ctx> DebugInfo<'ctx> {
+    /// Starts the debug info of a module whose main file is `main`. The data layout of the module
+    /// has to be set already, pointers are described with its pointer size.
+    pub fn new(module: &Module<'ctx>, main: &Path, is_optimized: bool) -> Self {
+        let main = absolute(main);
+        let (builder, unit) = module.create_debug_info_builder(
+            true,
+            // DWARF has no language code for RedditLang
+            DWARFSourceLanguage::C,
+            &main.file_name().unwrap().to_string_lossy(),
+            &main.parent().unwrap().to_string_lossy(),
+            "walter",
+            is_optimized,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+
+        // LLVM drops debug info from modules that don't say which version it is
+        let version = module
+            .get_context()
+            .i32_type()
+            .const_int(debug_metadata_version() as u64, false);
+        module.add_basic_value_flag("Debug Info Version", FlagBehavior::Warning, version);
+
+        let data_layout = module.get_data_layout();
+        let target_data = TargetData::create(data_layout.as_str().to_str().unwrap());
+
+        Self {
+            builder,
+            unit,
+            is_optimized,
+            pointer_bits: target_data.get_pointer_byte_size(None) as u64 * 8,
+            scopes: RefCell::default(),
+        }
+    }
+
+    fn file(&self, path: &Path) -> DIFile<'ctx> {
+        let path = absolute(path);
+        self.builder.create_file(
+            &path.file_name().unwrap().to_string_lossy(),
+            &path.parent().unwrap().to_string_lossy(),
+        )
+    }
+
+    /// Describes a value of an LLVM type. Strings and objects are both pointers to bytes.
+    fn r#type(&self, r#type: BasicTypeEnum<'ctx>) -> DIType<'ctx> {
+        let (name, bits, encoding) = match r#type {
+            BasicTypeEnum::IntType(x) => match x.get_bit_width() {
+                1 => ("Boolean".to_string(), 8, DW_ATE_BOOLEAN),
+                128 => ("Number".to_string(), 128, DW_ATE_SIGNED),
+                // Characters read out of strings
+                x => (format!("i{}", x), x as u64, DW_ATE_SIGNED),
+            },
+            _ => {
+                let char_type = self
+                    .builder
+                    .create_basic_type("char", 8, DW_ATE_SIGNED_CHAR, DIFlags::PUBLIC)
+                    .unwrap();
+                return self
+                    .builder
+                    .create_pointer_type(
+                        "String",
+                        char_type.as_type(),
+                        self.pointer_bits,
+                        0,
+                        AddressSpace::default(),
+                    )
+                    .as_type();
+            }
+        };
+
+        self.builder
+            .create_basic_type(&name, bits, encoding, DIFlags::PUBLIC)
+            .unwrap()
+            .as_type()
+    }
+}
+
+impl<'ctx> Compiler<'ctx> {
+    /// Gives `function` a subprogram defined at `line` of `file`. Its code is attributed to it until
+    /// `debug_leave_scope`.
+    pub fn debug_enter_function(
+        &self,
+        function: FunctionValue<'ctx>,
+        name: &str,
+        file: &Path,
+        line: usize,
+    ) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let file = debug_info.file(file);
+        let params: Vec<DIType> = function
+            .get_param_iter()
+            .map(|x| debug_info.r#type(x.get_type()))
+            .collect();
+        let returns = function
+            .get_type()
+            .get_return_type()
+            .map(|x| debug_info.r#type(x));
+        let subroutine_type =
+            debug_info
+                .builder
+                .create_subroutine_type(file, returns, &params, DIFlags::PUBLIC);
+
+        let subprogram = debug_info.builder.create_function(
+            debug_info.unit.as_debug_info_scope(),
+            name,
+            function.get_name().to_str().ok(),
+            file,
+            line as u32,
+            subroutine_type,
+            false,
+            true,
+            line as u32,
+            DIFlags::PUBLIC,
+            debug_info.is_optimized,
+        );
+        function.set_subprogram(subprogram);
+
+        self.debug_enter_scope(subprogram.as_debug_info_scope(), line);
+    }
+
+    /// Attributes the code built next to the top level of `file`, which is compiled into `main`
+    pub fn debug_enter_file(&self, file: &Path) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let parent = match debug_info.scopes.borrow().last() {
+            Some((x, _)) => *x,
+            None => return,
+        };
+        let block = debug_info
+            .builder
+            .create_lexical_block(parent, debug_info.file(file), 1, 1);
+
+        self.debug_enter_scope(block.as_debug_info_scope(), 1);
+    }
+
+    fn debug_enter_scope(&self, scope: DIScope<'ctx>, line: usize) {
+        let debug_info = self.debug_info.as_ref().unwrap();
+        let location =
+            debug_info
+                .builder
+                .create_debug_location(self.context, line as u32, 1, scope, None);
+
+        debug_info.scopes.borrow_mut().push((scope, location));
+        self.builder.set_current_debug_location(location);
+    }
+
+    /// Leaves the innermost function or file, the code around it continues where it left off
+    pub fn debug_leave_scope(&self) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut scopes = debug_info.scopes.borrow_mut();
+        scopes.pop();
+        match scopes.last() {
+            Some((_, x)) => self.builder.set_current_debug_location(*x),
+            None => self.builder.unset_current_debug_location(),
+        }
+    }
+
+    /// Attributes the code built next to `span`
+    pub fn debug_location(&self, span: &Span) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut scopes = debug_info.scopes.borrow_mut();
+        let (scope, location) = match scopes.last_mut() {
+            Some(x) => x,
+            None => return,
+        };
+        *location = debug_info.builder.create_debug_location(
+            self.context,
+            span.line as u32,
+            span.col as u32,
+            *scope,
+            None,
+        );
+        self.builder.set_current_debug_location(*location);
+    }
+
+    /// Describes a local variable stored at `pointer`. `arg` is the position of a parameter, from 1.
+    pub fn debug_declare_variable(
+        &self,
+        name: &str,
+        pointer: PointerValue<'ctx>,
+        r#type: BasicTypeEnum<'ctx>,
+        span: &Span,
+        arg: Option<u32>,
+    ) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let scope = match debug_info.scopes.borrow().last() {
+            Some((x, _)) => *x,
+            None => return,
+        };
+        let file = debug_info.file(&span.file);
+        let r#type = debug_info.r#type(r#type);
+        let variable = match arg {
+            Some(x) => debug_info.builder.create_parameter_variable(
+                scope,
+                name,
+                x,
+                file,
+                span.line as u32,
+                r#type,
+                true,
+                DIFlags::PUBLIC,
+            ),
+            None => debug_info.builder.create_auto_variable(
+                scope,
+                name,
+                file,
+                span.line as u32,
+                r#type,
+                true,
+                DIFlags::PUBLIC,
+                0,
+            ),
+        };
+
+        let location = debug_info.builder.create_debug_location(
+            self.context,
+            span.line as u32,
+            span.col as u32,
+            scope,
+            None,
+        );
+        debug_info.builder.insert_declare_at_end(
+            pointer,
+            Some(variable),
+            None,
+            location,
+            self.builder.get_insert_block().unwrap(),
+        );
+    }
+
+    /// Describes a top-level variable of a file, stored in `global`
+    pub fn debug_declare_global(
+        &self,
+        name: &str,
+        global: GlobalValue<'ctx>,
+        r#type: BasicTypeEnum<'ctx>,
+        span: &Span,
+    ) {
+        let debug_info = match &self.debug_info {
+            Some(x) => x,
+            None => return,
+        };
+
+        let expression = debug_info.builder.create_global_variable_expression(
+            debug_info.unit.as_debug_info_scope(),
+            name,
+            global.get_name().to_str().unwrap(),
+            debug_info.file(&span.file),
+            span.line as u32,
+            debug_info.r#type(r#type),
+            false,
+            None,
+            None,
+            0,
+        );
+        global.set_metadata(
+            expression.as_metadata_value(self.context),
+            self.context.get_kind_id("dbg"),
+        );
+    }
+
+    /// Resolves the debug info, it has to be done before the module is verified or emitted
+    pub fn debug_finalize(&self) {
+        if let Some(x) = &self.debug_info {
+            x.builder.finalize();
+        }
+    }
+}
@@ -1,41 +1,796 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
 use inkwell::{
-    builder::Builder, context::Context, module::Module, passes::PassManager, values::FunctionValue,
+    basic_block::BasicBlock,
+    builder::Builder,
+    context::Context,
+    module::Module,
+    passes::PassManager,
+    types::{BasicTypeEnum, IntType, PointerType},
+    values::{BasicValueEnum, FunctionValue, IntValue, PointerValue},
+    AddressSpace, FloatPredicate, IntPredicate,
 };
 
-use crate::parser::{Node, Tree};
+use crate::{
+    errors::{Code, Diagnostics, Reported},
+    loader::SourceFile,
+    parser::{Node, Span, Tree, Type},
+};
 
-use self::compile_node::Compile;
+use self::{
+    classes::{declare_class, define_class, ClassInfo},
+    compile_node::{declare_function, Compile, CompileValue},
+    debug_info::DebugInfo,
+    namespace::Exports,
+    symbols::SymbolTable,
+};
 
+pub mod bullets;
+pub mod classes;
 pub mod compile_node;
+pub mod debug_info;
+pub mod namespace;
+pub mod stdlib;
+pub mod symbols;
+pub mod trace;
 
 pub struct Compiler<'ctx> {
     pub context: &'ctx Context,
     pub builder: Builder<'ctx>,
     pub fpm: PassManager<FunctionValue<'ctx>>,
     pub module: Module<'ctx>,
+    pub symbols: RefCell<SymbolTable<'ctx>>,
+    /// Exit blocks of the loops being compiled and the scope depth outside of them, innermost last
+    pub loops: RefCell<Vec<(BasicBlock<'ctx>, usize)>>,
+    /// `wall` blocks of the `test`s being compiled, innermost last
+    pub catches: RefCell<Vec<BasicBlock<'ctx>>>,
+    /// Functions written in RedditLang, calling them can let a bullet escape
+    pub user_functions: RefCell<HashSet<String>>,
+    /// Classes of every file by their LLVM names
+    pub classes: RefCell<HashMap<String, ClassInfo<'ctx>>>,
+    /// Classes visible from the current file, RedditLang names mapped to LLVM names
+    pub class_names: RefCell<HashMap<String, String>>,
+    /// The class whose method is being compiled
+    pub current_class: RefCell<Option<String>>,
+    /// Prefix of the LLVM names of the current file's top-level items
+    pub prefix: RefCell<String>,
+    /// Functions visible from the current file, RedditLang names mapped to LLVM names
+    pub functions: RefCell<HashMap<String, String>>,
+    /// The standard library is not linked
+    pub nostd: bool,
+    /// Names of the functions in the standard library, even if it is not linked
+    pub std_functions: HashSet<String>,
+    /// Set in debug builds
+    pub debug_info: Option<DebugInfo<'ctx>>,
+    /// `debug` functions are not instrumented
+    pub release: bool,
+    /// The function being compiled is `debug`, changes to its variables are printed
+    pub trace: RefCell<bool>,
+    /// Errors found so far, a statement with an error is skipped and compiling goes on
+    pub diagnostics: Diagnostics,
+}
+
+impl<'ctx> Compiler<'ctx> {
+    pub fn new(
+        context: &'ctx Context,
+        module: Module<'ctx>,
+        builder: Builder<'ctx>,
+        fpm: PassManager<FunctionValue<'ctx>>,
+    ) -> Self {
+        Self {
+            context,
+            builder,
+            fpm,
+            module,
+            symbols: RefCell::default(),
+            loops: RefCell::default(),
+            catches: RefCell::default(),
+            user_functions: RefCell::default(),
+            classes: RefCell::default(),
+            class_names: RefCell::default(),
+            current_class: RefCell::default(),
+            prefix: RefCell::default(),
+            functions: RefCell::default(),
+            nostd: false,
+            std_functions: HashSet::new(),
+            debug_info: None,
+            release: false,
+            trace: RefCell::default(),
+            diagnostics: Diagnostics::default(),
+        }
+    }
+
+    /// The function the builder is currently inserting into
+    pub fn current_function(&self) -> FunctionValue<'ctx> {
+        self.builder
+            .get_insert_block()
+            .and_then(|x| x.get_parent())
+            .unwrap()
+    }
+
+    /// Branches to `block` unless the current block already ends in a terminator
+    pub fn build_branch_if_open(&self, block: BasicBlock<'ctx>) {
+        let terminated = self
+            .builder
+            .get_insert_block()
+            .and_then(|x| x.get_terminator())
+            .is_some();
+
+        if !terminated {
+            self.builder.build_unconditional_branch(block);
+        }
+    }
+
+    pub fn number_type(&self) -> IntType<'ctx> {
+        self.context.i128_type()
+    }
+
+    pub fn string_type(&self) -> PointerType<'ctx> {
+        self.context.i8_type().ptr_type(AddressSpace::default())
+    }
+
+    /// Converts a RedditLang type into its LLVM representation
+    pub fn resolve_type(&self, r#type: &Type) -> Result<BasicTypeEnum<'ctx>, Reported> {
+        if r#type.is_array {
+            return Ok(self.string_type().into());
+        }
+
+        let name = r#type.ident.0.as_str();
+        self.resolve_type_name(name).ok_or_else(|| {
+            self.diagnostics.error(
+                Code::Undefined,
+                &r#type.ident.1,
+                format!("Type `{}` not defined", name),
+            )
+        })
+    }
+
+    pub fn resolve_type_name(&self, name: &str) -> Option<BasicTypeEnum<'ctx>> {
+        match name {
+            "Number" => Some(self.number_type().into()),
+            "String" => Some(self.string_type().into()),
+            "Boolean" => Some(self.context.bool_type().into()),
+            // Objects are always behind a pointer
+            x if self.is_class(x) => Some(self.string_type().into()),
+            _ => None,
+        }
+    }
+
+    /// Extends an integer to a wider type, booleans are zero extended so `Yup` stays 1
+    pub fn build_int_widen(&self, value: IntValue<'ctx>, r#type: IntType<'ctx>) -> IntValue<'ctx> {
+        let width = value.get_type().get_bit_width();
+        if width >= r#type.get_bit_width() {
+            value
+        } else if width == 1 {
+            self.builder.build_int_z_extend(value, r#type, "widen")
+        } else {
+            self.builder.build_int_s_extend(value, r#type, "widen")
+        }
+    }
+
+    /// Converts a value to an `i1`, zero and `wat` are false and everything else is true
+    pub fn build_truthy(
+        &self,
+        value: BasicValueEnum<'ctx>,
+        span: &Span,
+    ) -> Result<IntValue<'ctx>, Reported> {
+        Ok(match value {
+            BasicValueEnum::IntValue(x) if x.get_type().get_bit_width() == 1 => x,
+            BasicValueEnum::IntValue(x) => self.builder.build_int_compare(
+                IntPredicate::NE,
+                x,
+                x.get_type().const_zero(),
+                "truthy",
+            ),
+            BasicValueEnum::FloatValue(x) => self.builder.build_float_compare(
+                FloatPredicate::ONE,
+                x,
+                x.get_type().const_zero(),
+                "truthy",
+            ),
+            BasicValueEnum::PointerValue(x) => self.builder.build_is_not_null(x, "truthy"),
+            _ => {
+                return Err(self.diagnostics.error(
+                    Code::TypeMismatch,
+                    span,
+                    "Value can't be used as a condition",
+                ))
+            }
+        })
+    }
+
+    /// Starts a new block for code following a terminator such as `sthu` or `spez`. Nothing
+    /// branches to it, but later statements still need somewhere to go.
+    pub fn build_dead_block(&self, name: &str) {
+        let block = self
+            .context
+            .append_basic_block(self.current_function(), name);
+        self.builder.position_at_end(block);
+    }
+
+    /// Allocates a stack slot in the entry block of the current function, so mem2reg can promote it
+    pub fn build_entry_alloca(
+        &self,
+        r#type: BasicTypeEnum<'ctx>,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        let entry = self.current_function().get_first_basic_block().unwrap();
+
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(x) => builder.position_before(&x),
+            None => builder.position_at_end(entry),
+        }
+
+        builder.build_alloca(r#type, name)
+    }
+}
+
+/// The zero value of a type, used for `wat` and global initializers
+pub fn zero_value(r#type: BasicTypeEnum) -> BasicValueEnum {
+    match r#type {
+        BasicTypeEnum::ArrayType(x) => x.const_zero().into(),
+        BasicTypeEnum::FloatType(x) => x.const_zero().into(),
+        BasicTypeEnum::IntType(x) => x.const_zero().into(),
+        BasicTypeEnum::PointerType(x) => x.const_zero().into(),
+        BasicTypeEnum::StructType(x) => x.const_zero().into(),
+        BasicTypeEnum::VectorType(x) => x.const_zero().into(),
+    }
 }
 
 pub fn llvm<'ctx>(compiler: &Compiler, tree: &Tree) {
+    // Signatures go first, so classes and functions can be used before they are defined
+    let classes: Vec<_> = tree
+        .iter()
+        .filter_map(|x| match x {
+            Node::Class(class) => declare_class(compiler, class).ok().map(|_| class),
+            _ => None,
+        })
+        .collect();
+    for class in classes {
+        define_class(compiler, class);
+    }
+    for node in tree {
+        if let Node::Function(function) = node {
+            let _ = declare_function(compiler, function);
+        }
+    }
+
     for node in tree {
-        llvm_one(&compiler, node);
+        // Errors are already in the diagnostics, the next statement is compiled anyway
+        let _ = llvm_one(compiler, node);
     }
 }
 
-pub fn llvm_one(compiler: &Compiler, node: &Node) {
+/// Compiles the top level of every file into the `main` function. Files must come after the files
+/// they import, like `loader::load` returns them.
+pub fn llvm_main(compiler: &Compiler, files: &[SourceFile]) {
+    let main_type = compiler.context.i32_type().fn_type(&[], false);
+    let main_fn = compiler.module.add_function("main", main_type, None);
+
+    let entry_basic_block = compiler.context.append_basic_block(main_fn, "entry");
+    compiler.builder.position_at_end(entry_basic_block);
+    if let Some(x) = files.last() {
+        compiler.debug_enter_function(main_fn, "main", &x.path, 1);
+    }
+
+    let mut exports: Vec<Exports> = vec![];
+    let mut objects = vec![];
+    for file in files {
+        compiler.enter_file(file, &exports);
+        compiler.debug_enter_file(&file.path);
+        llvm(compiler, &file.tree);
+        compiler.debug_leave_scope();
+
+        exports.push(compiler.file_exports(file));
+        objects.push(compiler.symbols.borrow().global_objects());
+    }
+
+    // Objects of the files that were compiled last go first
+    for object in objects.iter().rev().flatten() {
+        compiler.build_destroy(object, None);
+    }
+
+    compiler
+        .builder
+        .build_return(Some(&compiler.context.i32_type().const_zero()));
+    compiler.debug_leave_scope();
+    compiler.debug_finalize();
+}
+
+/// Compiles the body of a block in its own scope
+pub fn llvm_block(compiler: &Compiler, tree: &Tree) {
+    compiler.symbols.borrow_mut().push_scope();
+    llvm(compiler, tree);
+
+    let terminated = compiler
+        .builder
+        .get_insert_block()
+        .and_then(|x| x.get_terminator())
+        .is_some();
+    if !terminated {
+        let depth = compiler.symbols.borrow().depth();
+        compiler.build_destroy_objects(depth - 1, false, None);
+    }
+
+    compiler.symbols.borrow_mut().pop_scope();
+}
+
+pub fn llvm_one(compiler: &Compiler, node: &Node) -> Result<(), Reported> {
+    compiler.debug_location(node.span());
+
     match node {
-        Node::Loop(_) => todo!(),
-        Node::Break(_) => todo!(),
-        Node::Function(_) => todo!(),
+        Node::Loop(r#loop) => r#loop.compile(compiler),
+        Node::Break(r#break) => r#break.compile(compiler),
+        Node::Function(function) => function.compile(compiler),
         Node::Call(call) => call.compile(compiler),
-        Node::Throw(_) => todo!(),
-        Node::Import(_) => todo!(),
-        Node::Module(_) => todo!(),
-        Node::TryCatch(_) => todo!(),
-        Node::Variable(_) => todo!(),
-        Node::Assignment(_) => todo!(),
-        Node::If(_) => todo!(),
-        Node::Class(_) => todo!(),
-        Node::Return(_) => todo!(),
-        Node::Expr(_) => todo!(),
+        Node::Throw(throw) => throw.compile(compiler),
+        // Imports are loaded by `loader::load` before anything is compiled
+        Node::Import(_) | Node::Module(_) => {
+            if !compiler.symbols.borrow().is_top_level() {
+                return Err(compiler.diagnostics.error(
+                    Code::MisplacedModule,
+                    node.span(),
+                    "`weneed`, `bringme` and `subreddit` can only be used at the top level",
+                ));
+            }
+            Ok(())
+        }
+        Node::TryCatch(try_catch) => try_catch.compile(compiler),
+        Node::Variable(variable) => variable.compile(compiler),
+        Node::Assignment(assignment) => assignment.compile(compiler),
+        Node::If(if_block) => if_block.compile(compiler),
+        Node::Class(class) => class.compile(compiler),
+        Node::Return(r#return) => r#return.compile(compiler),
+        Node::Expr(expr) => expr.compile_value(compiler).map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::{
+        context::Context,
+        passes::PassManager,
+        targets::{InitializationConfig, Target, TargetTriple},
+        OptimizationLevel,
+    };
+
+    use std::path::Path;
+
+    use super::{bullets::UNCAUGHT_EXIT_CODE, debug_info::DebugInfo, llvm_main, Compiler};
+    use crate::{
+        errors::{Code, Diagnostics},
+        loader::SourceFile,
+        parse_file,
+    };
+
+    /// Compiles a program as `main.rl`, errors are left in `diagnostics` of the compiler
+    fn compile<'ctx>(context: &'ctx Context, source: &str, debug_info: bool) -> Compiler<'ctx> {
+        compile_files(context, &[("main", source)], debug_info, false, None)
+    }
+
+    /// Compiles `(module, source)` files, each importing the ones before it. The last one is main.
+    /// The module is built for the host unless there is a `triple`.
+    fn compile_files<'ctx>(
+        context: &'ctx Context,
+        sources: &[(&str, &str)],
+        debug_info: bool,
+        release: bool,
+        triple: Option<&str>,
+    ) -> Compiler<'ctx> {
+        let diagnostics = Diagnostics::default();
+        let files: Vec<SourceFile> = sources
+            .iter()
+            .enumerate()
+            .map(|(index, (module, source))| {
+                let path = Path::new(module).with_extension("rl");
+                SourceFile {
+                    tree: parse_file(&path, source, &diagnostics).unwrap(),
+                    path,
+                    module: module.to_string(),
+                    imports: (0..index).collect(),
+                }
+            })
+            .collect();
+
+        let module = context.create_module("test");
+        if let Some(x) = triple {
+            module.set_triple(&TargetTriple::create(x));
+        }
+        let builder = context.create_builder();
+        let fpm = PassManager::create(&module);
+        let mut compiler = Compiler::new(context, module, builder, fpm);
+        compiler.diagnostics = diagnostics;
+        compiler.release = release;
+        if debug_info {
+            let main = &files.last().unwrap().path;
+            compiler.debug_info = Some(DebugInfo::new(&compiler.module, main, false));
+        }
+
+        llvm_main(&compiler, &files);
+        compiler
+    }
+
+    /// Compiles a program and runs its `main` function, returning the exit code
+    fn run(source: &str) -> i32 {
+        Target::initialize_native(&InitializationConfig::default()).unwrap();
+
+        let context = Context::create();
+        let compiler = compile(&context, source, false);
+        assert!(compiler.diagnostics.is_empty());
+        compiler.module.verify().unwrap();
+
+        let engine = compiler
+            .module
+            .create_jit_execution_engine(OptimizationLevel::None)
+            .unwrap();
+        unsafe {
+            engine
+                .get_function::<unsafe extern "C" fn() -> i32>("main")
+                .unwrap()
+                .call()
+        }
+    }
+
+    /// The `is` / `but` / `isnt` chain from `stress_test.rl`, reporting which branch was taken
+    fn if_chain(value: i32) -> i32 {
+        run(&format!(
+            r"meth value ∑ {value}
+meth result ∑ 0
+is value ⅀ 69 {{
+    result ∑ 1
+}} but value ⅀ 70 {{
+    result ∑ 2
+}} isnt {{
+    result ∑ 3
+}}
+spez result
+"
+        ))
+    }
+
+    #[test]
+    fn if_branch() {
+        assert_eq!(if_chain(69), 1);
+    }
+
+    #[test]
+    fn else_if_branch() {
+        assert_eq!(if_chain(70), 2);
+    }
+
+    #[test]
+    fn else_branch() {
+        assert_eq!(if_chain(71), 3);
+    }
+
+    #[test]
+    fn anti_equality_and_truthiness() {
+        let source = r"meth value ∑ 0
+meth result ∑ 0
+is value ≠ 69 {
+    is value {
+        result ∑ 1
+    } isnt {
+        result ∑ 10
+    }
+}
+spez result
+";
+        assert_eq!(run(source), 10);
+    }
+
+    #[test]
+    fn strings_are_compared_by_their_characters() {
+        let source = r#"meth result ∑ 0
+meth nothing damn String ∑ wat
+is "abc" ⅀ "abc" {
+    result ∑ result ⨋ 1
+}
+is "abc" ≠ "abd" {
+    result ∑ result ⨋ 10
+}
+is nothing ≠ "abc" {
+    result ∑ result ⨋ 100
+}
+spez result
+"#;
+        assert_eq!(run(source), 111);
+    }
+
+    #[test]
+    fn returned_booleans_are_zero_extended() {
+        assert_eq!(run("meth x ∑ 2\nspez x ⅀ 2\n"), 1);
+    }
+
+    #[test]
+    fn foolean_literals() {
+        let source = r"meth result ∑ 0
+is Yup {
+    result ∑ result ⨋ 1
+}
+is Nope {
+    result ∑ result ⨋ 10
+}
+is Dunno {
+    result ∑ result ⨋ 100
+}
+meth yeet damn Boolean ∑ Yeet
+spez result
+";
+        assert_eq!(run(source), 1);
+    }
+
+    #[test]
+    fn math_and_indexing() {
+        // Left to right: ((1 ⨋ 2) * 3 ⊕ 1) ⎲ 2 is 4, "abc"[2] is `b`
+        let source = r#"meth x ∑ (1 ⨋ 2) * 3 ⊕ 1 ⎲ 2
+spez x ⨋ ("abc"[2]) - 98
+"#;
+        assert_eq!(run(source), 4);
+    }
+
+    #[test]
+    fn bullet_crosses_function() {
+        let source = r#"callmeonmycellphone risky damn Number(x damn Number,) {
+    is x ⅀ 1 {
+        shoot "too risky"
+    }
+    spez x
+}
+meth result ∑ 0
+test {
+    result ∑ call risky(2,)
+    result ∑ call risky(1,)
+    result ∑ 100
+} wall bullet {
+    result ∑ result ⨋ 40
+}
+spez result
+"#;
+        assert_eq!(run(source), 42);
+    }
+
+    #[test]
+    fn uncaught_bullet() {
+        let source = r#"shoot "nobody walls this"
+spez 0
+"#;
+        assert_eq!(run(source), UNCAUGHT_EXIT_CODE as i32);
+    }
+
+    #[test]
+    fn windows_reports_bullets_without_dprintf() {
+        let source = "callmeonmycellphone f() {\n}\ncall f()\nspez 0\n";
+        let context = Context::create();
+        let compiler = compile_files(
+            &context,
+            &[("main", source)],
+            false,
+            false,
+            Some("x86_64-pc-windows-msvc"),
+        );
+        assert!(compiler.diagnostics.is_empty());
+        compiler.module.verify().unwrap();
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("@_write("));
+        assert!(ir.contains("@snprintf("));
+        assert!(!ir.contains("@dprintf("));
+    }
+
+    const LAB: &str = r#"meth destroyed ∑ 0
+school Lab {
+    bar meth prop damn Number ∑ wat
+    meth secret ∑ 5
+
+    callmeonmycellphone cooK(start damn Number,) {
+        prop ∑ start
+    }
+
+    callmeonmycellphone get damn Number() {
+        spez prop ⨋ secret
+    }
+
+    callmeonmycellphone snoRt() {
+        destroyed ∑ destroyed ⨋ 1
+    }
+}
+"#;
+
+    #[test]
+    fn class_fields_and_methods() {
+        let source =
+            format!("{LAB}meth lab ∑ call Lab(37,)\nspez call lab.get() ⨋ (lab[\"prop\"])\n");
+        assert_eq!(run(&source), 79);
+    }
+
+    #[test]
+    fn method_called_init_is_not_the_initializer() {
+        let source = r"school Counter {
+    bar meth count damn Number ∑ 5
+
+    callmeonmycellphone init damn Number() {
+        spez count ⨋ 1
+    }
+}
+meth counter ∑ call Counter()
+spez call counter.init()
+";
+        assert_eq!(run(source), 6);
+    }
+
+    #[test]
+    fn destructor_runs_when_block_ends() {
+        let source = format!(
+            "{LAB}repeatdatshid {{\n    meth lab ∑ call Lab(1,)\n    sthu\n}}\nis 1 {{\n    meth lab ∑ call Lab(2,)\n}}\nspez destroyed\n"
+        );
+        assert_eq!(run(&source), 2);
+    }
+
+    #[test]
+    fn assigning_an_object_destroys_the_previous_one() {
+        let source = format!(
+            "{LAB}meth lab ∑ call Lab(1,)\nlab ∑ call Lab(2,)\nlab ∑ call Lab(3,)\nspez destroyed\n"
+        );
+        assert_eq!(run(&source), 2);
+    }
+
+    #[test]
+    fn modules_with_the_same_file_name() {
+        let util = "bar callmeonmycellphone x damn Number() {\n    spez 1\n}\nschool Box {\n    bar meth size ∑ 2\n}\n";
+        let main = "meth box ∑ call Box()\nspez call x() ⨋ (box[\"size\"])\n";
+        let context = Context::create();
+        let compiler = compile_files(
+            &context,
+            &[("a/util", util), ("b/util", util), ("main", main)],
+            false,
+            false,
+            None,
+        );
+        assert!(compiler.diagnostics.is_empty());
+        compiler.module.verify().unwrap();
+
+        assert!(compiler.module.get_function("a/util.x").is_some());
+        assert!(compiler.module.get_function("b/util.x").is_some());
+        assert!(compiler.module.get_function("a/util.Box.<init>").is_some());
+        assert!(compiler.module.get_function("b/util.Box.<init>").is_some());
+    }
+
+    #[test]
+    fn main_file_items_do_not_clash_with_libc() {
+        // Constructing calls `malloc`, calling a function checks for bullets with `dprintf`
+        let source = r"meth free ∑ 2
+callmeonmycellphone malloc damn Number() {
+    spez free
+}
+callmeonmycellphone dprintf damn Number() {
+    spez 3
+}
+school Box {
+    bar meth size ∑ 1
+}
+meth box ∑ call Box()
+spez call malloc() ⨋ call dprintf()
+";
+        assert_eq!(run(source), 5);
+    }
+
+    #[test]
+    fn debug_info_describes_functions_and_variables() {
+        let source = format!(
+            "{LAB}callmeonmycellphone add damn Number(a damn Number, b damn Number,) {{\n    meth sum ∑ a ⨋ b\n    spez sum\n}}\nmeth lab ∑ call Lab(1,)\nspez call add(call lab.get(), 2,)\n"
+        );
+        let context = Context::create();
+        let compiler = compile(&context, &source, true);
+        assert!(compiler.diagnostics.is_empty());
+        compiler.module.verify().unwrap();
+
+        let ir = compiler.module.print_to_string().to_string();
+        assert!(ir.contains("!DISubprogram(name: \"add\""));
+        assert!(ir.contains("!DILocalVariable(name: \"b\", arg: 2"));
+        assert!(ir.contains("!DILocalVariable(name: \"sum\""));
+        // Top-level variables are globals, not locals of `main`
+        assert!(ir.contains("!DIGlobalVariable(name: \"lab\""));
+        assert!(!ir.contains("!DILocalVariable(name: \"lab\""));
+    }
+
+    const COUNT: &str = r#"debug callmeonmycellphone count damn Number(to damn Number,) {
+    meth i ∑ 0
+    meth done ∑ i ⅀ to
+    meth name ∑ "counting"
+    repeatdatshid {
+        done ∑ i ⅀ to
+        is done {
+            sthu
+        }
+        i ∑ i ⨋ 1
+    }
+    spez i
+}
+spez call count(5,)
+"#;
+
+    #[test]
+    fn debug_function_keeps_its_result() {
+        assert_eq!(run(COUNT), 5);
+    }
+
+    #[test]
+    fn debug_function_is_traced_unless_release() {
+        // Bullet checks call `dprintf` too, traces are the calls that print a `.trace` string
+        let traces = |release: bool| {
+            let context = Context::create();
+            let compiler = compile_files(&context, &[("main", COUNT)], false, release, None);
+            assert!(compiler.diagnostics.is_empty());
+            let ir = compiler.module.print_to_string().to_string();
+            ir.lines()
+                .filter(|x| x.contains("@dprintf(") && x.contains("@.trace"))
+                .count()
+        };
+
+        // `i` is declared and assigned, `done` twice, `name` once
+        assert_eq!(traces(false), 5);
+        assert_eq!(traces(true), 0);
+    }
+
+    #[test]
+    fn arguments_of_the_wrong_type_are_reported() {
+        let source = format!(
+            "{LAB}callmeonmycellphone add damn Number(a damn Number,) {{\n    spez a\n}}\nmeth lab ∑ call Lab(\"one\",)\nmeth x ∑ call add(1 ⅀ 1,)\nspez 0\n"
+        );
+        let context = Context::create();
+        let compiler = compile(&context, &source, false);
+        let diagnostics = compiler.diagnostics.take();
+        let errors: Vec<(Code, &str)> = diagnostics
+            .iter()
+            .map(|x| (x.code, x.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    Code::TypeMismatch,
+                    "Argument 1 of `main.Lab.cooK` is a value of the wrong type"
+                ),
+                (
+                    Code::TypeMismatch,
+                    "Argument 1 of `add` is a value of the wrong type"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let source = r#"callmeonmycellphone twice(x, x,) {
+}
+meth a ∑ missing
+bar meth exported ∑ missing
+meth b ∑ "one" ⨋ 1
+call nothing()
+sthu
+spez 0
+"#;
+        let context = Context::create();
+        let compiler = compile(&context, source, false);
+        let codes: Vec<Code> = compiler.diagnostics.take().iter().map(|x| x.code).collect();
+        // Checks while parsing come first, then errors of the parse tree, then compile errors.
+        // `sthu` is only reported once, by `check_breaks`. The `bar` variable that failed isn't
+        // exported.
+        assert_eq!(
+            codes,
+            [
+                Code::BreakOutsideLoop,
+                Code::DuplicateArgument,
+                Code::UndefinedVariable,
+                Code::UndefinedVariable,
+                Code::InvalidMath,
+                Code::Undefined,
+            ]
+        );
     }
 }
@@ -0,0 +1,103 @@
+//! Every file gets its own namespace. Its top-level functions, variables and classes are prefixed
+//! with the module path in LLVM. Only the `bar` functions and variables are visible to files that
+//! import it, classes always are.
+
+use std::collections::HashMap;
+
+use inkwell::values::FunctionValue;
+
+use crate::{
+    loader::SourceFile,
+    parser::{FunctionMod, Node, VariableMod},
+};
+
+use super::{
+    symbols::{Scope, Symbol},
+    Compiler,
+};
+
+/// The items of a file that the files importing it can see
+#[derive(Debug, Default)]
+pub struct Exports<'ctx> {
+    /// RedditLang names mapped to LLVM names
+    pub functions: HashMap<String, String>,
+    pub variables: HashMap<String, Symbol<'ctx>>,
+    /// RedditLang names mapped to LLVM names
+    pub classes: HashMap<String, String>,
+}
+
+impl<'ctx> Compiler<'ctx> {
+    /// The LLVM name of a top-level item of the current file
+    pub fn mangle(&self, name: &str) -> String {
+        format!("{}{}", self.prefix.borrow(), name)
+    }
+
+    /// Finds a function visible from the current file, including the standard library
+    pub fn get_function(&self, name: &str) -> Option<FunctionValue<'ctx>> {
+        match self.functions.borrow().get(name) {
+            Some(x) => self.module.get_function(x),
+            // RedditLang functions of other files have to be imported
+            None if self.user_functions.borrow().contains(name) => None,
+            None => self.module.get_function(name),
+        }
+    }
+
+    /// Starts compiling `file`, bringing in the items exported by the files it imports. Items of
+    /// the main file are prefixed too, so they don't clash with `main` or functions from libc.
+    pub fn enter_file(&self, file: &SourceFile, exports: &[Exports<'ctx>]) {
+        self.prefix.replace(format!("{}.", file.module));
+
+        let mut functions = HashMap::new();
+        let mut globals = Scope::default();
+        let mut classes = HashMap::new();
+        for import in &file.imports {
+            functions.extend(exports[*import].functions.clone());
+            globals.symbols.extend(exports[*import].variables.clone());
+            classes.extend(exports[*import].classes.clone());
+        }
+
+        self.functions.replace(functions);
+        self.class_names.replace(classes);
+        self.symbols.borrow_mut().replace_globals(globals);
+    }
+
+    /// The `bar` functions and variables and the classes of a file that has just been compiled
+    pub fn file_exports(&self, file: &SourceFile) -> Exports<'ctx> {
+        let mut exports = Exports::default();
+        let functions = self.functions.borrow();
+        let symbols = self.symbols.borrow();
+        let class_names = self.class_names.borrow();
+
+        for node in &file.tree {
+            match node {
+                // Functions and variables that failed to compile are missing, their errors are
+                // already reported
+                Node::Function(x)
+                    if x.modifiers.iter().any(|x| matches!(x, FunctionMod::Public)) =>
+                {
+                    let name = &x.declaration.ident.0;
+                    if let Some(function) = functions.get(name) {
+                        exports.functions.insert(name.clone(), function.clone());
+                    }
+                }
+                Node::Variable(x)
+                    if x.modifiers.iter().any(|x| matches!(x, VariableMod::Public)) =>
+                {
+                    let name = &x.declaration.ident.0;
+                    if let Some(symbol) = symbols.globals.symbols.get(name) {
+                        exports.variables.insert(name.clone(), symbol.clone());
+                    }
+                }
+                Node::Class(x) => {
+                    // Classes that failed to declare are missing
+                    if let Some(class) = class_names.get(&x.ident.0) {
+                        exports.classes.insert(x.ident.0.clone(), class.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        exports
+    }
+}
@@ -0,0 +1,39 @@
+//! The functions of libstd are declared from its manifest, so the compiler doesn't need to know
+//! about them.
+
+use inkwell::types::{BasicMetadataTypeEnum, BasicType};
+
+use crate::stdlib::Manifest;
+
+use super::Compiler;
+
+impl<'ctx> Compiler<'ctx> {
+    /// Adds every function of the manifest to the module
+    pub fn declare_stdlib(&self, manifest: &Manifest) -> Result<(), String> {
+        let resolve = |name: &str, function: &str| {
+            self.resolve_type_name(name).ok_or_else(|| {
+                format!(
+                    "Unknown type `{}` in the declaration of `{}`",
+                    name, function
+                )
+            })
+        };
+
+        for function in &manifest.functions {
+            let args = function
+                .args
+                .iter()
+                .map(|x| resolve(x, &function.name).map(BasicMetadataTypeEnum::from))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let function_type = match &function.returns {
+                Some(x) => resolve(x, &function.name)?.fn_type(&args, function.variadic),
+                None => self.context.void_type().fn_type(&args, function.variadic),
+            };
+            self.module
+                .add_function(&function.name, function_type, None);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use inkwell::{types::BasicTypeEnum, values::PointerValue};
+
+/// A variable that lives in memory, either a stack slot or a global
+#[derive(Debug, Clone)]
+pub struct Symbol<'ctx> {
+    pub pointer: PointerValue<'ctx>,
+    pub r#type: BasicTypeEnum<'ctx>,
+    /// The class of the object this variable points to, if any
+    pub class: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Scope<'ctx> {
+    pub symbols: HashMap<String, Symbol<'ctx>>,
+    /// Variables that own an object constructed in this scope, destroyed in reverse when it ends
+    pub objects: Vec<Symbol<'ctx>>,
+}
+
+/// Variables visible at the current point of compilation, innermost scope last
+#[derive(Debug, Default)]
+pub struct SymbolTable<'ctx> {
+    pub globals: Scope<'ctx>,
+    scopes: Vec<Scope<'ctx>>,
+}
+
+impl<'ctx> SymbolTable<'ctx> {
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Swaps in the globals of another file
+    pub fn replace_globals(&mut self, globals: Scope<'ctx>) {
+        self.globals = globals;
+    }
+
+    /// Hides every local scope while a function body is compiled, only globals stay visible.
+    /// The returned scopes are given back to `leave_function` afterwards.
+    pub fn enter_function(&mut self) -> Vec<Scope<'ctx>> {
+        std::mem::take(&mut self.scopes)
+    }
+
+    pub fn leave_function(&mut self, scopes: Vec<Scope<'ctx>>) {
+        self.scopes = scopes;
+    }
+
+    /// True if no block is open, declarations here become globals
+    pub fn is_top_level(&self) -> bool {
+        self.scopes.is_empty()
+    }
+
+    /// Number of open block scopes
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    fn innermost(&mut self) -> &mut Scope<'ctx> {
+        match self.scopes.last_mut() {
+            Some(x) => x,
+            None => &mut self.globals,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, symbol: Symbol<'ctx>) {
+        self.innermost().symbols.insert(name.to_string(), symbol);
+    }
+
+    /// Marks a variable of the innermost scope as the owner of an object
+    pub fn insert_object(&mut self, symbol: Symbol<'ctx>) {
+        self.innermost().objects.push(symbol);
+    }
+
+    /// The scope that declares `name`
+    fn declaring_scope(&mut self, name: &str) -> Option<&mut Scope<'ctx>> {
+        let index = self
+            .scopes
+            .iter()
+            .rposition(|x| x.symbols.contains_key(name));
+        match index {
+            Some(x) => Some(&mut self.scopes[x]),
+            None => Some(&mut self.globals).filter(|x| x.symbols.contains_key(name)),
+        }
+    }
+
+    /// True if the variable `name` owns an object
+    pub fn is_object(&self, name: &str) -> bool {
+        let scope = self
+            .scopes
+            .iter()
+            .rev()
+            .chain([&self.globals])
+            .find(|x| x.symbols.contains_key(name));
+        scope.is_some_and(|x| {
+            let pointer = x.symbols[name].pointer;
+            x.objects.iter().any(|object| object.pointer == pointer)
+        })
+    }
+
+    /// Makes the variable `name` own an object, or stop owning one, in the scope that declares it
+    pub fn set_object(&mut self, name: &str, owner: bool) {
+        if let Some(scope) = self.declaring_scope(name) {
+            let symbol = scope.symbols[name].clone();
+            scope.objects.retain(|x| x.pointer != symbol.pointer);
+            if owner {
+                scope.objects.push(symbol);
+            }
+        }
+    }
+
+    /// Objects owned by scopes deeper than `depth`, in the order they should be destroyed
+    pub fn objects_from(&self, depth: usize) -> Vec<Symbol<'ctx>> {
+        self.scopes[depth.min(self.scopes.len())..]
+            .iter()
+            .rev()
+            .flat_map(|x| x.objects.iter().rev().cloned())
+            .collect()
+    }
+
+    /// Objects owned by global variables, in the order they should be destroyed
+    pub fn global_objects(&self) -> Vec<Symbol<'ctx>> {
+        self.globals.objects.iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol<'ctx>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|x| x.symbols.get(name))
+            .or_else(|| self.globals.symbols.get(name))
+            .cloned()
+    }
+}
@@ -0,0 +1,72 @@
+//! Instrumentation of `debug` functions. Every `meth` they declare or assign is printed to stderr
+//! with its new value and the position of the statement that changed it. Release builds leave it
+//! out.
+
+use inkwell::values::{BasicMetadataValueEnum, BasicValueEnum};
+
+use crate::parser::Span;
+
+use super::Compiler;
+
+impl<'ctx> Compiler<'ctx> {
+    /// Prints `name = value` if the function being compiled is `debug`. Objects of `class` are
+    /// printed as their address.
+    pub fn build_trace(
+        &self,
+        name: &str,
+        value: BasicValueEnum<'ctx>,
+        class: Option<&str>,
+        span: &Span,
+    ) {
+        if !*self.trace.borrow() {
+            return;
+        }
+
+        let (format, value): (String, BasicMetadataValueEnum) = match value {
+            BasicValueEnum::IntValue(x) if x.get_type().get_bit_width() == 1 => {
+                let yup = self.builder.build_global_string_ptr("Yup", ".yup");
+                let nope = self.builder.build_global_string_ptr("Nope", ".nope");
+                let value = self.builder.build_select(
+                    x,
+                    yup.as_pointer_value(),
+                    nope.as_pointer_value(),
+                    "trace_bool",
+                );
+                ("%s".to_string(), value.into())
+            }
+            // Numbers are printed as 64 bits
+            BasicValueEnum::IntValue(x) => {
+                let i64_type = self.context.i64_type();
+                let value = if x.get_type().get_bit_width() > 64 {
+                    self.builder.build_int_truncate(x, i64_type, "trace_number")
+                } else {
+                    self.build_int_widen(x, i64_type)
+                };
+                ("%lld".to_string(), value.into())
+            }
+            BasicValueEnum::PointerValue(x) => match class {
+                Some(class) => (format!("{} at %p", class), x.into()),
+                None => {
+                    let wat = self.builder.build_global_string_ptr("wat", ".wat");
+                    let is_null = self.builder.build_is_null(x, "is_wat");
+                    let value = self.builder.build_select(
+                        is_null,
+                        wat.as_pointer_value(),
+                        x,
+                        "trace_string",
+                    );
+                    ("%s".to_string(), value.into())
+                }
+            },
+            _ => return,
+        };
+
+        let prefix = format!("{}: {} = ", span, name).replace('%', "%%");
+        let format = self
+            .builder
+            .build_global_string_ptr(&format!("{}{}\n", prefix, format), ".trace")
+            .as_pointer_value();
+
+        self.build_eprintf(format, &[value], "trace");
+    }
+}
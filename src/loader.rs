@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    errors::{Code, Diagnostic, Diagnostics},
+    parse_file,
+    parser::{Import, Node, Span, Term, Tree},
+};
+
+/// Modules of the standard library, these are linked in and need no source file
+pub const STD_MODULES: [&str; 3] = ["io", "time", "sys"];
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// Path relative to the source directory without the extension, like `a/util`. Unlike
+    /// `subreddit` names and file names it is unique, so it prefixes the LLVM names of the file.
+    pub module: String,
+    pub tree: Tree,
+    /// Files imported by this one, as indices into the list returned by `load`
+    pub imports: Vec<usize>,
+}
+
+struct Loader<'a> {
+    src_dir: &'a Path,
+    nostd: bool,
+    diagnostics: &'a Diagnostics,
+    files: Vec<SourceFile>,
+    indices: HashMap<PathBuf, usize>,
+    /// Files currently being loaded, used to find import cycles
+    stack: Vec<PathBuf>,
+    /// A file could not be loaded, so the program can't be compiled
+    incomplete: bool,
+}
+
+/// Loads `main` and every file it imports. Each file is parsed once, and files always come after
+/// the files they import, so `main` is last. Standard library modules can't be imported in
+/// `nostd` builds.
+///
+/// Errors are added to `diagnostics`. None is returned if a file is missing or has a syntax error,
+/// other errors leave a program that can still be compiled to find more of them.
+pub fn load(
+    src_dir: &Path,
+    main: &Path,
+    nostd: bool,
+    diagnostics: &Diagnostics,
+) -> Option<Vec<SourceFile>> {
+    let mut loader = Loader {
+        src_dir,
+        nostd,
+        diagnostics,
+        files: vec![],
+        indices: HashMap::new(),
+        stack: vec![],
+        incomplete: false,
+    };
+
+    let main = match main.canonicalize() {
+        Ok(x) => x,
+        Err(_) => {
+            diagnostics.message(format!("No {} found", main.display()));
+            return None;
+        }
+    };
+    loader.load_file(main, None);
+
+    if loader.incomplete {
+        return None;
+    }
+    Some(loader.files)
+}
+
+impl<'a> Loader<'a> {
+    /// Loads the file at `path`, which was imported at `import`
+    fn load_file(&mut self, path: PathBuf, import: Option<&Span>) -> Option<usize> {
+        if let Some(x) = self.indices.get(&path) {
+            return Some(*x);
+        }
+
+        if let Some(start) = self.stack.iter().position(|x| x == &path) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&path])
+                .map(|x| self.display(x))
+                .collect();
+            self.fail(import, format!("Import cycle: {}", cycle.join(" -> ")));
+            return None;
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(x) => {
+                self.fail(import, format!("Can't read {}: {}", self.display(&path), x));
+                return None;
+            }
+        };
+        let tree = match parse_file(&path, &source, self.diagnostics) {
+            Ok(x) => x,
+            Err(_) => {
+                self.incomplete = true;
+                return None;
+            }
+        };
+
+        let import_paths: Vec<(PathBuf, Span)> = tree
+            .iter()
+            .filter_map(|x| match x {
+                Node::Import(x) => self.resolve(x).map(|path| (path, x.span.clone())),
+                _ => None,
+            })
+            .collect();
+
+        self.stack.push(path.clone());
+        let imports = import_paths
+            .into_iter()
+            .filter_map(|(path, span)| self.load_file(path, Some(&span)))
+            .collect();
+        self.stack.pop();
+
+        self.files.push(SourceFile {
+            module: self.module(&path),
+            path: path.clone(),
+            tree,
+            imports,
+        });
+        self.indices.insert(path, self.files.len() - 1);
+        Some(self.files.len() - 1)
+    }
+
+    /// Reports an error that keeps a file from being loaded, at the import that led to it
+    fn fail(&mut self, import: Option<&Span>, message: String) {
+        self.incomplete = true;
+        match import {
+            Some(x) => {
+                self.diagnostics.error(Code::Import, x, message);
+            }
+            None => {
+                self.diagnostics.message(message);
+            }
+        }
+    }
+
+    /// Finds the file an import refers to, `None` for standard library modules
+    fn resolve(&mut self, import: &Import) -> Option<PathBuf> {
+        let path = match &import.path {
+            Term::String(x, _) => x,
+            x => {
+                self.fail(Some(x.span()), "Imports must be strings".to_string());
+                return None;
+            }
+        };
+
+        if let Some(module) = path.strip_prefix("std/") {
+            if self.nostd {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Code::Nostd,
+                        &import.span,
+                        format!("`{}` is not available in nostd builds", path),
+                    )
+                    .with_note("the project is built with `nostd` in walter.yml or `--nostd`"),
+                );
+            } else if !STD_MODULES.contains(&module) {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Code::Import,
+                        &import.span,
+                        format!("The standard library has no module `{}`", path),
+                    )
+                    .with_help(format!(
+                        "the modules of the standard library are {}",
+                        STD_MODULES.join(", ")
+                    )),
+                );
+            }
+            return None;
+        }
+
+        let mut file = self.src_dir.join(path);
+        if file.extension().is_none() {
+            file.set_extension("rl");
+        }
+
+        match file.canonicalize() {
+            Ok(x) => Some(x),
+            Err(_) => {
+                self.fail(Some(&import.span), format!("Module `{}` not found", path));
+                None
+            }
+        }
+    }
+
+    /// The module name of the file at `path`
+    fn module(&self, path: &Path) -> String {
+        let src_dir = self.src_dir.canonicalize().unwrap_or_default();
+        let components: Vec<_> = path
+            .strip_prefix(src_dir)
+            .unwrap_or(path)
+            .with_extension("")
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy().to_string())
+            .collect();
+        components.join("/")
+    }
+
+    /// A path relative to the source directory, for messages
+    fn display(&self, path: &Path) -> String {
+        let src_dir = self.src_dir.canonicalize().unwrap_or_default();
+        path.strip_prefix(src_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::load;
+    use crate::errors::{Code, Diagnostics};
+
+    #[test]
+    fn std_is_not_available_in_nostd_builds() {
+        let dir = env::temp_dir().join(format!("walter-nostd-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.rl");
+        fs::write(&main, "weneed \"std/io\"\n").unwrap();
+
+        let diagnostics = Diagnostics::default();
+        let nostd = load(&dir, &main, true, &diagnostics).map(|x| x.len());
+        let nostd_diagnostics = diagnostics.take();
+        let std = load(&dir, &main, false, &diagnostics).map(|x| x.len());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(nostd, Some(1));
+        assert_eq!(nostd_diagnostics.len(), 1);
+        assert_eq!(nostd_diagnostics[0].code, Code::Nostd);
+        assert_eq!(
+            nostd_diagnostics[0].message,
+            "`std/io` is not available in nostd builds"
+        );
+        assert_eq!(std, Some(1));
+        assert!(diagnostics.is_empty());
+    }
+}
@@ -20,7 +20,8 @@ impl log::Log for ColoredLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            println!(
+            // stderr, so the output of `walter brwww` is only the program's
+            eprintln!(
                 "{}{} {}",
                 record
                     .level()
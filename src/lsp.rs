@@ -0,0 +1,885 @@
+//! `walter lsp`, a language server for editors. It speaks JSON-RPC over stdin and stdout and only
+//! looks at the open files: diagnostics come from parsing them, not from compiling the project.
+
+use std::{collections::HashMap, error::Error, path::PathBuf};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as LspNotification, PublishDiagnostics,
+    },
+    request::{
+        Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as LspRequest,
+    },
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolResponse,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, Location, MarkupContent,
+    MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolKind, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+
+use crate::{
+    errors::{Diagnostic, Diagnostics},
+    parse_file,
+    parser::{Declaration, Expr, Function, Ident, IfNode, Node, Span, Term, Tree},
+};
+
+const KEYWORDS: [(&str, &str); 25] = [
+    ("callmeonmycellphone", "Declares a function"),
+    (
+        "debug",
+        "Prints the variables of a function when they change",
+    ),
+    ("bar", "Makes a function or variable public"),
+    ("spez", "Returns from a function"),
+    ("call", "Calls a function"),
+    ("meth", "Declares a variable"),
+    ("damn", "Gives a declaration a type"),
+    ("school", "Declares a class"),
+    ("is", "If"),
+    ("but", "Else if"),
+    ("isnt", "Else"),
+    ("repeatdatshid", "Loops forever"),
+    ("sthu", "Leaves a loop"),
+    ("test", "Catches bullets shot in its block"),
+    ("wall", "Handles a caught bullet"),
+    ("shoot", "Shoots a bullet"),
+    ("weneed", "Imports a module"),
+    ("bringme", "Imports a module"),
+    ("subreddit", "Names the module of a file"),
+    ("wat", "Null"),
+    ("Yup", "True"),
+    ("Nope", "False"),
+    ("Dunno", "Null Foolean"),
+    ("Huh", "IO failure Foolean"),
+    ("Yeet", "Random Foolean"),
+];
+
+/// An open file as it was when it last parsed
+struct Document {
+    text: String,
+    tree: Tree,
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    /// Open files that parsed at least once. Files with a syntax error keep their last tree.
+    documents: HashMap<Url, Document>,
+}
+
+/// Serves the editor on stdin and stdout until it shuts the server down
+pub fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        connection: &connection,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = server.respond(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                // A malformed notification only loses that file's update
+                let method = notification.method.clone();
+                if let Err(x) = server.notify(notification) {
+                    log::error!("Can't handle {}: {}", method, x);
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread stops once nothing can send to it anymore
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Answers a request with `handler`, or with an error if its parameters are invalid
+fn handle<R: LspRequest>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(x) => Response::new_ok(request.id, handler(x)),
+        Err(x) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, x.to_string()),
+    }
+}
+
+impl Server<'_> {
+    fn respond(&self, request: Request) -> Response {
+        let method = request.method.clone();
+        match method.as_str() {
+            DocumentSymbolRequest::METHOD => handle::<DocumentSymbolRequest>(request, |x| {
+                let document = self.documents.get(&x.text_document.uri)?;
+                Some(DocumentSymbolResponse::Nested(symbols(
+                    &document.tree,
+                    &document.text,
+                    false,
+                )))
+            }),
+            GotoDefinition::METHOD => handle::<GotoDefinition>(request, |x| {
+                let position = &x.text_document_position_params;
+                let (_, definition, document) = self.lookup(position)?;
+                Some(GotoDefinitionResponse::Scalar(Location::new(
+                    position.text_document.uri.clone(),
+                    range(&document.text, &definition.ident.1),
+                )))
+            }),
+            HoverRequest::METHOD => handle::<HoverRequest>(request, |x| {
+                let (ident, definition, document) =
+                    self.lookup(&x.text_document_position_params)?;
+                Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("```redditlang\n{}\n```", definition.signature),
+                    }),
+                    range: Some(range(&document.text, &ident.1)),
+                })
+            }),
+            Completion::METHOD => handle::<Completion>(request, |_| {
+                let items = KEYWORDS
+                    .iter()
+                    .map(|(keyword, detail)| CompletionItem {
+                        label: keyword.to_string(),
+                        kind: Some(CompletionItemKind::KEYWORD),
+                        detail: Some(detail.to_string()),
+                        ..Default::default()
+                    })
+                    .collect();
+                Some(CompletionResponse::Array(items))
+            }),
+            x => {
+                let message = format!("Unknown request {}", x);
+                Response::new_err(request.id, ErrorCode::MethodNotFound as i32, message)
+            }
+        }
+    }
+
+    fn notify(&mut self, notification: Notification) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                // The whole file is sent on every change
+                match params.content_changes.into_iter().last() {
+                    Some(x) => (params.text_document.uri, x.text),
+                    None => return Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                return self.publish(params.text_document.uri, vec![]);
+            }
+            _ => return Ok(()),
+        };
+
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let diagnostics = Diagnostics::default();
+        if let Ok(tree) = parse_file(&path, &text, &diagnostics) {
+            let document = Document {
+                text: text.clone(),
+                tree,
+            };
+            self.documents.insert(uri.clone(), document);
+        }
+
+        let diagnostics = diagnostics
+            .take()
+            .iter()
+            .map(|x| to_lsp(x, &uri, &text))
+            .collect();
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(
+        &self,
+        uri: Url,
+        diagnostics: Vec<lsp_types::Diagnostic>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+
+    /// The identifier at a position and where it is defined
+    fn lookup(
+        &self,
+        position: &TextDocumentPositionParams,
+    ) -> Option<(&Ident, Definition<'_>, &Document)> {
+        let document = self.documents.get(&position.text_document.uri)?;
+        let offset = offset(&document.text, position.position);
+
+        let (ident, role) = ident_at(&document.tree, offset)?;
+        let mut definitions = vec![];
+        definitions_in(&document.tree, (0, usize::MAX), false, &mut definitions);
+
+        let index = match role {
+            Role::Name => innermost(&definitions, |x| x.ident.0 == ident.0 && visible(x, offset)),
+            Role::Type => innermost(&definitions, |x| {
+                x.kind == Kind::Class && x.ident.0 == ident.0
+            }),
+            Role::Method(receiver) => {
+                let offset = receiver.1.start;
+                let variable = innermost(&definitions, |x| {
+                    x.kind == Kind::Variable && x.ident.0 == receiver.0 && visible(x, offset)
+                })?;
+                let class = definitions[variable].class?;
+                let class = innermost(&definitions, |x| {
+                    x.kind == Kind::Class && x.ident.0 == class && visible(x, offset)
+                })?;
+                // Methods are declared in the scope of their class
+                let body = definitions[class].ident.1.start;
+                innermost(&definitions, |x| {
+                    x.kind == Kind::Method
+                        && x.ident.0 == ident.0
+                        && x.scope.0 <= body
+                        && body <= x.scope.1
+                })
+            }
+        }?;
+        Some((ident, definitions.swap_remove(index), document))
+    }
+}
+
+/// True if `definition` can be used at `offset`
+fn visible(definition: &Definition, offset: usize) -> bool {
+    let in_scope = definition.scope.0 <= offset && offset <= definition.scope.1;
+    // Functions and classes can be used before they are declared
+    let declared = matches!(definition.kind, Kind::Function | Kind::Method | Kind::Class)
+        || definition.ident.1.start <= offset;
+    in_scope && declared
+}
+
+/// The index of the definition in the innermost scope that `matches`, the latest one if several
+/// are in the same scope
+fn innermost(definitions: &[Definition], matches: impl Fn(&Definition) -> bool) -> Option<usize> {
+    definitions
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| matches(x))
+        .min_by_key(|(_, x)| (x.scope.1 - x.scope.0, usize::MAX - x.ident.1.start))
+        .map(|(i, _)| i)
+}
+
+fn to_lsp(diagnostic: &Diagnostic, uri: &Url, text: &str) -> lsp_types::Diagnostic {
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message += &format!("\nnote: {}", note);
+    }
+    for help in &diagnostic.help {
+        message += &format!("\nhelp: {}", help);
+    }
+
+    let related = diagnostic
+        .secondary
+        .iter()
+        .map(|x| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), range(text, &x.span)),
+            message: x.message.clone(),
+        })
+        .collect();
+
+    lsp_types::Diagnostic {
+        range: range(text, &diagnostic.span),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: Some(NumberOrString::String(diagnostic.code.as_str().to_string())),
+        source: Some("walter".to_string()),
+        message,
+        related_information: Some(related),
+        ..Default::default()
+    }
+}
+
+/// An LSP position, which counts lines from 0 and UTF-16 code units from the start of the line
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn range(text: &str, span: &Span) -> Range {
+    Range::new(position(text, span.start), position(text, span.end))
+}
+
+/// The byte offset of an LSP position
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(x) => line_start += x + 1,
+            None => return text.len(),
+        }
+    }
+
+    let mut units = 0;
+    for (i, x) in text[line_start..].char_indices() {
+        if units >= position.character as usize || x == '\n' {
+            return line_start + i;
+        }
+        units += x.len_utf16();
+    }
+    text.len()
+}
+
+/// How a declaration is written, without the body
+fn declaration(declaration: &Declaration) -> String {
+    match &declaration.r#type {
+        Some(x) => format!(
+            "{} damn {}{}",
+            declaration.ident.0,
+            x.ident.0,
+            if x.is_array { "[]" } else { "" }
+        ),
+        None => declaration.ident.0.clone(),
+    }
+}
+
+fn function_signature(function: &Function) -> String {
+    let args: Vec<String> = function
+        .args
+        .iter()
+        .map(|x| format!("{},", declaration(x)))
+        .collect();
+    format!(
+        "callmeonmycellphone {}({})",
+        declaration(&function.declaration),
+        args.join(" ")
+    )
+}
+
+#[allow(deprecated)]
+fn symbol(
+    name: &Ident,
+    kind: SymbolKind,
+    detail: Option<String>,
+    span: &Span,
+    text: &str,
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name: name.0.clone(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: range(text, span),
+        selection_range: range(text, &name.1),
+        children: Some(children),
+    }
+}
+
+/// The functions, classes and variables of a tree. Those in loops, branches and `test` blocks
+/// belong to the tree around them.
+fn symbols(tree: &Tree, text: &str, in_class: bool) -> Vec<DocumentSymbol> {
+    let mut found = vec![];
+    for node in tree {
+        match node {
+            Node::Function(x) => found.push(symbol(
+                &x.declaration.ident,
+                if in_class {
+                    SymbolKind::METHOD
+                } else {
+                    SymbolKind::FUNCTION
+                },
+                x.declaration.r#type.as_ref().map(|x| x.ident.0.clone()),
+                &x.span,
+                text,
+                symbols(&x.body, text, false),
+            )),
+            Node::Class(x) => found.push(symbol(
+                &x.ident,
+                SymbolKind::CLASS,
+                None,
+                &x.span,
+                text,
+                symbols(&x.body, text, true),
+            )),
+            Node::Variable(x) => found.push(symbol(
+                &x.declaration.ident,
+                if in_class {
+                    SymbolKind::FIELD
+                } else {
+                    SymbolKind::VARIABLE
+                },
+                x.declaration.r#type.as_ref().map(|x| x.ident.0.clone()),
+                &x.span,
+                text,
+                vec![],
+            )),
+            Node::Loop(x) => found.extend(symbols(&x.body, text, false)),
+            Node::If(x) => {
+                for x in &x.if_nodes {
+                    found.extend(symbols(if_body(x), text, false));
+                }
+            }
+            Node::TryCatch(x) => {
+                found.extend(symbols(&x.r#try.0, text, false));
+                found.extend(symbols(&x.catch.1, text, false));
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+fn if_body(node: &IfNode) -> &Tree {
+    match node {
+        IfNode::If(x) => &x.body,
+        IfNode::ElseIf(x) => &x.body,
+        IfNode::Else(x) => &x.body,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Function,
+    Method,
+    Class,
+    Variable,
+}
+
+/// A name declared in a document
+struct Definition<'a> {
+    ident: &'a Ident,
+    kind: Kind,
+    /// The bytes of the document the name can be used in
+    scope: (usize, usize),
+    /// How it is declared, shown on hover
+    signature: String,
+    /// The class of a variable, if its type or value names one
+    class: Option<&'a str>,
+}
+
+/// The class a variable holds an object of, from its `damn` type or the constructor it is set to
+fn class_of<'a>(declaration: &'a Declaration, value: Option<&'a Expr>) -> Option<&'a str> {
+    match (&declaration.r#type, value) {
+        (Some(x), _) if !x.is_array => Some(&x.ident.0),
+        (Some(_), _) => None,
+        (None, Some(Expr::Term(Term::Call(x)))) if x.receiver.is_none() => Some(&x.ident.0),
+        (None, Some(Expr::Term(Term::Expr(x)))) => class_of(declaration, Some(x.as_ref())),
+        (None, _) => None,
+    }
+}
+
+/// Collects the names declared in a tree, which can be used in `scope`
+fn definitions_in<'a>(
+    tree: &'a Tree,
+    scope: (usize, usize),
+    in_class: bool,
+    definitions: &mut Vec<Definition<'a>>,
+) {
+    for node in tree {
+        match node {
+            Node::Function(x) => {
+                definitions.push(Definition {
+                    ident: &x.declaration.ident,
+                    kind: if in_class {
+                        Kind::Method
+                    } else {
+                        Kind::Function
+                    },
+                    scope,
+                    signature: function_signature(x),
+                    class: None,
+                });
+
+                let body = (x.span.start, x.span.end);
+                for arg in &x.args {
+                    definitions.push(Definition {
+                        ident: &arg.ident,
+                        kind: Kind::Variable,
+                        scope: body,
+                        signature: declaration(arg),
+                        class: class_of(arg, None),
+                    });
+                }
+                definitions_in(&x.body, body, false, definitions);
+            }
+            Node::Class(x) => {
+                definitions.push(Definition {
+                    ident: &x.ident,
+                    kind: Kind::Class,
+                    scope,
+                    signature: format!("school {}", x.ident.0),
+                    class: None,
+                });
+                definitions_in(&x.body, (x.span.start, x.span.end), true, definitions);
+            }
+            Node::Variable(x) => {
+                let public = if x.modifiers.is_empty() { "" } else { "bar " };
+                definitions.push(Definition {
+                    ident: &x.declaration.ident,
+                    kind: Kind::Variable,
+                    scope,
+                    signature: format!("{}meth {}", public, declaration(&x.declaration)),
+                    class: class_of(&x.declaration, Some(&x.value)),
+                })
+            }
+            Node::Loop(x) => {
+                definitions_in(&x.body, (x.span.start, x.span.end), false, definitions)
+            }
+            Node::If(x) => {
+                for node in &x.if_nodes {
+                    let scope = (x.span.start, x.span.end);
+                    definitions_in(if_body(node), scope, false, definitions);
+                }
+            }
+            Node::TryCatch(x) => {
+                let scope = (x.span.start, x.span.end);
+                definitions_in(&x.r#try.0, scope, false, definitions);
+                if let Some(ident) = &x.catch.0 {
+                    definitions.push(Definition {
+                        ident,
+                        kind: Kind::Variable,
+                        scope,
+                        signature: format!("wall {}", ident.0),
+                        class: None,
+                    });
+                }
+                definitions_in(&x.catch.1, scope, false, definitions);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// What an identifier refers to
+#[derive(Debug, Clone, Copy)]
+enum Role<'a> {
+    /// A variable, function or class
+    Name,
+    /// The method in `call lab.method()`, called on the receiver `lab`
+    Method(&'a Ident),
+    /// The class after `damn`
+    Type,
+}
+
+fn contains(span: &Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+/// Finds the identifier that `offset` is in
+fn ident_at(tree: &Tree, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    tree.iter().find_map(|x| ident_in_node(x, offset))
+}
+
+fn ident_in_declaration(declaration: &Declaration, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    if contains(&declaration.ident.1, offset) {
+        return Some((&declaration.ident, Role::Name));
+    }
+    declaration
+        .r#type
+        .as_ref()
+        .filter(|x| contains(&x.ident.1, offset))
+        .map(|x| (&x.ident, Role::Type))
+}
+
+fn ident_in_node(node: &Node, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    if !contains(node.span(), offset) {
+        return None;
+    }
+
+    match node {
+        Node::Function(x) => std::iter::once(&x.declaration)
+            .chain(&x.args)
+            .find_map(|x| ident_in_declaration(x, offset))
+            .or_else(|| ident_at(&x.body, offset)),
+        Node::Class(x) if contains(&x.ident.1, offset) => Some((&x.ident, Role::Name)),
+        Node::Class(x) => ident_at(&x.body, offset),
+        Node::Variable(x) => {
+            ident_in_declaration(&x.declaration, offset).or_else(|| ident_in_expr(&x.value, offset))
+        }
+        Node::Assignment(x) if contains(&x.ident.1, offset) => Some((&x.ident, Role::Name)),
+        Node::Assignment(x) => ident_in_expr(&x.value, offset),
+        Node::Call(x) => ident_in_call(x, offset),
+        Node::Throw(x) => ident_in_expr(&x.value, offset),
+        Node::Return(x) => ident_in_expr(&x.value, offset),
+        Node::Loop(x) => ident_at(&x.body, offset),
+        Node::If(x) => x.if_nodes.iter().find_map(|node| match node {
+            IfNode::If(x) => ident_in_expr(&x.expr, offset).or_else(|| ident_at(&x.body, offset)),
+            IfNode::ElseIf(x) => {
+                ident_in_expr(&x.expr, offset).or_else(|| ident_at(&x.body, offset))
+            }
+            IfNode::Else(x) => ident_at(&x.body, offset),
+        }),
+        Node::TryCatch(x) => ident_at(&x.r#try.0, offset)
+            .or_else(|| {
+                x.catch
+                    .0
+                    .as_ref()
+                    .filter(|x| contains(&x.1, offset))
+                    .map(|x| (x, Role::Name))
+            })
+            .or_else(|| ident_at(&x.catch.1, offset)),
+        Node::Expr(x) => ident_in_expr(x, offset),
+        Node::Break(_) | Node::Import(_) | Node::Module(_) => None,
+    }
+}
+
+fn ident_in_call(call: &crate::parser::Call, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    if let Some(receiver) = call.receiver.as_ref().filter(|x| contains(&x.1, offset)) {
+        return Some((receiver, Role::Name));
+    }
+    if contains(&call.ident.1, offset) {
+        let role = match &call.receiver {
+            Some(x) => Role::Method(x),
+            None => Role::Name,
+        };
+        return Some((&call.ident, role));
+    }
+    call.args.iter().find_map(|x| ident_in_expr(x, offset))
+}
+
+fn ident_in_expr(expr: &Expr, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    if !contains(expr.span(), offset) {
+        return None;
+    }
+
+    match expr {
+        Expr::BinaryExpr(x) => x
+            .terms
+            .iter()
+            .find_map(|x| ident_in_term(&x.operand, offset)),
+        Expr::ConditionalExpr(x) => x
+            .terms
+            .iter()
+            .find_map(|x| ident_in_term(&x.operand, offset)),
+        Expr::IndexExpr(x) => ident_in_term(&x.term, offset),
+        Expr::Term(x) => ident_in_term(x, offset),
+        Expr::Null(_) => None,
+    }
+}
+
+fn ident_in_term(term: &Term, offset: usize) -> Option<(&Ident, Role<'_>)> {
+    match term {
+        Term::Ident(x) if contains(&x.1, offset) => Some((x, Role::Name)),
+        Term::Call(x) => ident_in_call(x, offset),
+        Term::Expr(x) => ident_in_expr(x, offset),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use lsp_server::Connection;
+    use lsp_types::{
+        Position, SymbolKind, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
+    use super::{ident_at, offset, position, symbols, Document, Role, Server};
+    use crate::{errors::Diagnostics, parse_file};
+
+    const SOURCE: &str = r#"meth count ∑ 1
+school Lab {
+    meth size ∑ 2
+
+    callmeonmycellphone get damn Number() {
+        spez size
+    }
+}
+school Box {
+    callmeonmycellphone get damn Number() {
+        spez 3
+    }
+}
+callmeonmycellphone twice damn Number(count damn Number,) {
+    spez call double(count,) ⨋ count
+}
+callmeonmycellphone double damn Number(n damn Number,) {
+    spez n ⨋ n
+}
+meth lab ∑ call Lab()
+meth box damn Box ∑ wat
+spez call lab.get() ⨋ call box.get() ⨋ count
+"#;
+
+    fn document(text: &str) -> Document {
+        let diagnostics = Diagnostics::default();
+        let tree = parse_file(Path::new("main.rl"), text, &diagnostics).unwrap();
+        Document {
+            text: text.to_string(),
+            tree,
+        }
+    }
+
+    /// The byte offset of the `n`th `needle` in `SOURCE`, counting from 0
+    fn find(needle: &str, n: usize) -> usize {
+        SOURCE.match_indices(needle).nth(n).unwrap().0
+    }
+
+    /// Looks up the `n`th `needle` in `SOURCE`. Returns the line its definition is on and how it
+    /// is shown on hover.
+    fn lookup(needle: &str, n: usize) -> Option<(u32, String)> {
+        let (connection, _client) = Connection::memory();
+        let uri = Url::parse("file:///project/src/main.rl").unwrap();
+        let mut server = Server {
+            connection: &connection,
+            documents: HashMap::new(),
+        };
+        server.documents.insert(uri.clone(), document(SOURCE));
+
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: position(SOURCE, find(needle, n)),
+        };
+        let (_, definition, document) = server.lookup(&params)?;
+        let line = position(&document.text, definition.ident.1.start).line;
+        Some((line, definition.signature))
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "meth a ∑ 1\nmeth 𝔟 ∑ a\n";
+        let b = text.find('𝔟').unwrap();
+        // ∑ is 1 unit and 3 bytes, 𝔟 is 2 units and 4 bytes
+        assert_eq!(position(text, text.find('1').unwrap()), Position::new(0, 9));
+        assert_eq!(position(text, b), Position::new(1, 5));
+        assert_eq!(position(text, b + 4), Position::new(1, 7));
+        assert_eq!(position(text, text.len()), Position::new(2, 0));
+
+        for x in [0, 11, b, b + 4, text.rfind('a').unwrap(), text.len()] {
+            assert_eq!(offset(text, position(text, x)), x);
+        }
+        // Past the end of a line or of the text
+        assert_eq!(
+            offset(text, Position::new(0, 100)),
+            text.find('\n').unwrap()
+        );
+        assert_eq!(offset(text, Position::new(9, 0)), text.len());
+    }
+
+    #[test]
+    fn finds_the_ident_under_the_cursor() {
+        let document = document(SOURCE);
+        let ident = |offset| ident_at(&document.tree, offset).map(|(x, role)| (x.0.clone(), role));
+
+        let (name, role) = ident(find("double", 0) + 2).unwrap();
+        assert_eq!(name, "double");
+        assert!(matches!(role, Role::Name));
+
+        let (name, role) = ident(find("get", 2)).unwrap();
+        assert_eq!(name, "get");
+        assert!(matches!(role, Role::Method(x) if x.0 == "lab"));
+
+        let (name, role) = ident(find("Box", 1)).unwrap();
+        assert_eq!(name, "Box");
+        assert!(matches!(role, Role::Type));
+
+        assert!(ident(find("spez", 0)).is_none());
+    }
+
+    #[test]
+    fn arguments_shadow_globals() {
+        assert_eq!(
+            lookup("count", 3),
+            Some((13, "count damn Number".to_string()))
+        );
+        assert_eq!(lookup("count", 4), Some((0, "meth count".to_string())));
+    }
+
+    #[test]
+    fn functions_can_be_used_before_they_are_declared() {
+        assert_eq!(
+            lookup("double", 0),
+            Some((
+                16,
+                "callmeonmycellphone double damn Number(n damn Number,)".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn methods_are_found_through_the_receiver() {
+        // `lab` is set to a `Lab`, `box` is declared as a `Box`
+        assert_eq!(lookup("get", 2).unwrap().0, 4);
+        assert_eq!(lookup("get", 3).unwrap().0, 9);
+        assert_eq!(lookup("Box", 1), Some((8, "school Box".to_string())));
+    }
+
+    #[test]
+    fn methods_of_unknown_classes_are_not_found() {
+        let (connection, _client) = Connection::memory();
+        let uri = Url::parse("file:///project/src/main.rl").unwrap();
+        let text = format!("{}meth n ∑ 1\ncall n.get()\n", SOURCE);
+        let mut server = Server {
+            connection: &connection,
+            documents: HashMap::new(),
+        };
+        server.documents.insert(uri.clone(), document(&text));
+
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: position(&text, text.rfind("get").unwrap()),
+        };
+        assert!(server.lookup(&params).is_none());
+    }
+
+    #[test]
+    fn symbols_nest_methods_in_classes() {
+        let symbols = symbols(&document(SOURCE).tree, SOURCE, false);
+        let outline: Vec<(&str, SymbolKind)> =
+            symbols.iter().map(|x| (x.name.as_str(), x.kind)).collect();
+        assert_eq!(
+            outline,
+            [
+                ("count", SymbolKind::VARIABLE),
+                ("Lab", SymbolKind::CLASS),
+                ("Box", SymbolKind::CLASS),
+                ("twice", SymbolKind::FUNCTION),
+                ("double", SymbolKind::FUNCTION),
+                ("lab", SymbolKind::VARIABLE),
+                ("box", SymbolKind::VARIABLE),
+            ]
+        );
+
+        let lab = symbols[1].children.as_ref().unwrap();
+        assert_eq!(lab[0].name, "size");
+        assert_eq!(lab[0].kind, SymbolKind::FIELD);
+        assert_eq!(lab[1].name, "get");
+        assert_eq!(lab[1].kind, SymbolKind::METHOD);
+        assert_eq!(lab[1].detail.as_deref(), Some("Number"));
+        assert_eq!(lab[1].range.start, Position::new(4, 4));
+        assert_eq!(lab[1].selection_range.start, Position::new(4, 24));
+    }
+}
@@ -1,34 +1,42 @@
 use crate::{
-    errors::error,
-    llvm::{llvm, Compiler},
+    errors::{Diagnostic, Diagnostics, MessageFormat, Reported},
+    from_pair::ParseContext,
+    llvm::{debug_info::DebugInfo, llvm_main, Compiler},
 };
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use git::clone_else_pull;
+use git2::Repository;
 use inkwell::{
     context::Context,
-    passes::PassManager,
-    targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine},
-    AddressSpace, OptimizationLevel,
+    passes::{PassManager, PassManagerBuilder},
+    targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple},
+    OptimizationLevel,
 };
-use parser::{parse, Tree};
+use parser::{check_breaks, check_modules, parse, Tree};
 use pest::Parser as PestParser;
 use pest_derive::Parser as PestParser;
-use project::Project;
+use project::{OptLevel, Project};
 use std::{
-    env, fs,
+    env,
+    fmt::Display,
+    fs,
     hash::Hash,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, ExitStatus},
 };
+use stdlib::Manifest;
 
 pub mod errors;
 pub mod from_pair;
 pub mod git;
+pub mod linker;
 pub mod llvm;
+pub mod loader;
 pub mod logger;
+pub mod lsp;
 pub mod parser;
 pub mod project;
+pub mod stdlib;
 pub mod utils;
 
 #[derive(PestParser)]
@@ -46,210 +54,479 @@ struct Args {
 enum Commands {
     /// Builds a program
     Cook {
-        /// Enables release mode, longer build but more optimizations.
-        #[arg(short, long)]
-        release: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    /// Builds and runs a program
+    Brwww {
+        #[command(flatten)]
+        options: BuildOptions,
+        /// Arguments passed to the program
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Manages the cached standard library
+    Stdlib {
+        #[command(subcommand)]
+        command: StdlibCommands,
     },
-    // /// Builds and runs a program
-    // Brwww {
-    //     /// Enables release mode, longer build but more optimizations.
-    //     #[arg(short, long)]
-    //     release: bool,
-    // },
     /// Creates a new walter project
     New {
         #[arg(short, long)]
         name: String,
+        /// Initializes a git repository in the project
+        #[arg(long)]
+        git: bool,
     },
+    /// Starts a language server for editors, on stdin and stdout
+    Lsp,
 }
 
-fn get_project() -> Project {
-    match Project::from_path(env::current_dir().unwrap().as_path()) {
-        Some(x) => x,
-        None => {
-            log::error!("No valid {} found.", "walter.yml".bold());
-            std::process::exit(1);
+#[derive(clap::Args, Debug)]
+struct BuildOptions {
+    /// Enables release mode, longer build but more optimizations.
+    #[arg(short, long)]
+    release: bool,
+    /// Links with this program instead of the default C compiler
+    #[arg(long)]
+    linker: Option<String>,
+    /// A checkout of the standard library or a prebuilt libstd.a
+    #[arg(long)]
+    stdlib: Option<PathBuf>,
+    /// Builds without the standard library
+    #[arg(long)]
+    nostd: bool,
+    /// Target triple to build for, such as aarch64-unknown-linux-gnu
+    #[arg(long)]
+    target: Option<String>,
+    /// CPU to build for, defaults to the host CPU or `generic` when cross compiling
+    #[arg(long)]
+    cpu: Option<String>,
+    /// CPU features to enable or disable, such as +avx2,-sse4.1
+    #[arg(long)]
+    features: Option<String>,
+    /// Also writes these intermediate artifacts to the build directory. The LLVM ones all come
+    /// from the module that is linked, after it is verified and optimized. `llvm-ir` also writes
+    /// the module before that as `<name>.unopt.ll`, even if it has errors or fails to verify.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    emit: Vec<Emit>,
+    /// How errors in the program are printed
+    #[arg(long, value_enum, default_value_t)]
+    message_format: MessageFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum Emit {
+    /// Textual LLVM IR
+    LlvmIr,
+    /// LLVM bitcode
+    Bc,
+    /// Assembly for the target
+    Asm,
+    /// The object file that is linked
+    Obj,
+    /// Debug dump of the parsed files
+    Ast,
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::LlvmIr => "ll",
+            Emit::Bc => "bc",
+            Emit::Asm => "s",
+            Emit::Obj => "redd.it.o",
+            Emit::Ast => "ast",
         }
     }
 }
 
-const STDLIB_URL: &str = "https://github.com/elijah629/redditlang-std";
+#[derive(Subcommand, Debug)]
+enum StdlibCommands {
+    /// Downloads the latest standard library and builds it
+    Update,
+}
 
-fn build_libstd() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let walter_dir = dirs::home_dir().unwrap().join(".walter");
-    let std_dir = walter_dir.join("stdlib");
+fn get_project(format: MessageFormat) -> Project {
+    match Project::from_path(env::current_dir().unwrap().as_path()) {
+        Some(x) => x,
+        None => fail(format, format!("No valid {} found.", "walter.yml".bold())),
+    }
+}
 
-    fs::create_dir_all(&walter_dir)?;
+/// Reports an error of a build that isn't about a place in the code, then exits
+fn fail(format: MessageFormat, message: impl Display) -> ! {
+    format.error(message);
+    std::process::exit(1);
+}
 
-    // Make sure libstd is up to date
-    clone_else_pull(STDLIB_URL, &std_dir, "main").expect("Failed to clone libstd repo");
+fn llvm_opt_level(opt_level: OptLevel) -> OptimizationLevel {
+    match opt_level {
+        OptLevel::Speed(0) => OptimizationLevel::None,
+        OptLevel::Speed(1) => OptimizationLevel::Less,
+        OptLevel::Speed(2) | OptLevel::Size | OptLevel::MinSize => OptimizationLevel::Default,
+        OptLevel::Speed(_) => OptimizationLevel::Aggressive,
+    }
+}
 
-    Command::new("cargo")
-        .arg("build")
-        .arg("--release")
-        .current_dir(&std_dir)
-        .output()?;
+/// Builds the project in the current directory, returning the path of the executable
+fn cook(options: &BuildOptions) -> PathBuf {
+    let format = options.message_format;
+    // Tools read the errors printed as JSON, they have no colors
+    if format == MessageFormat::Json {
+        colored::control::set_override(false);
+    }
 
-    fs::rename(
-        &std_dir.join("target/release/libstd.a"),
-        &std_dir.join("libstd.a"),
-    )?;
+    let release = options.release;
+    let project = get_project(format);
+    let nostd = options.nostd || project.config.nostd;
+    let target = options.target.as_ref().or(project.config.target.as_ref());
+    let (std_path, manifest) = if nostd {
+        // Only used to tell apart stdlib functions in diagnostics, so it works without a stdlib
+        let source = stdlib::source(options.stdlib.as_deref(), &project);
+        let manifest = Some(&source)
+            .filter(|x| x.exists())
+            .and_then(|x| Manifest::read(x).ok())
+            .unwrap_or_else(Manifest::builtin);
+        (None, manifest)
+    } else {
+        match stdlib::locate(
+            options.stdlib.as_deref(),
+            &project,
+            target.map(|x| x.as_str()),
+        ) {
+            Ok((path, manifest)) => (Some(path), manifest),
+            Err(x) => fail(format, format!("Error building libstd: {}", x)),
+        }
+    };
 
-    Command::new("cargo")
-        .arg("clean")
-        .current_dir(&std_dir)
-        .output()?;
+    let project_dir = Path::new(&project.path);
+    let build_dir = project_dir
+        .join("build")
+        .join(if release { "release" } else { "debug" });
+    let src_dir = project_dir.join("src");
 
-    Ok(std_dir.join("libstd.a"))
-}
+    fs::create_dir_all(&build_dir).unwrap();
 
-fn main() {
-    let args = Args::parse();
-    logger::init().unwrap();
+    log::info!("Lexing/Parsing");
 
-    match args.command {
-        Commands::Cook { release } => {
-            let project = get_project();
-            let std_path = match build_libstd() {
-                Ok(x) => x,
-                Err(x) => {
-                    log::error!("Error building libstd: {:?}", x);
-                    std::process::exit(1);
-                }
-            };
+    let diagnostics = Diagnostics::default();
+    let files = match loader::load(&src_dir, &src_dir.join("main.rl"), nostd, &diagnostics) {
+        Some(x) => x,
+        None => {
+            diagnostics.report(format);
+            std::process::exit(1);
+        }
+    };
+
+    let emit_path =
+        |emit: Emit| build_dir.join(format!("{}.{}", project.config.name, emit.extension()));
+    // Exits if an artifact couldn't be written, and mentions the ones that were asked for
+    let written_to = |path: &Path, emit: Emit, result: Result<(), String>| {
+        let path = path.display().to_string();
+        match result {
+            Err(x) => fail(format, format!("Can't write {}: {}", path, x)),
+            Ok(()) if options.emit.contains(&emit) => log::info!("Wrote {}", path.bold()),
+            Ok(()) => {}
+        }
+    };
+    let written =
+        |emit: Emit, result: Result<(), String>| written_to(&emit_path(emit), emit, result);
 
-            let project_dir = Path::new(&project.path);
-            let build_dir =
-                project_dir
-                    .join("build")
-                    .join(if release { "release" } else { "debug" });
-            let src_dir = project_dir.join("src");
-            let main_file = src_dir.join("main.rl");
-            let main_file = fs::read_to_string(&main_file).unwrap();
+    if options.emit.contains(&Emit::Ast) {
+        let result = fs::write(emit_path(Emit::Ast), format!("{:#?}", files));
+        written(Emit::Ast, result.map_err(|x| x.to_string()));
+    }
 
-            fs::create_dir_all(&build_dir).unwrap();
+    let context = Context::create();
+    let module = context.create_module("main");
+    let builder = context.create_builder();
+
+    let fpm = PassManager::create(&module);
+    let mpm = PassManager::create(());
+
+    // Debug builds run no passes at all
+    let opt_level = project.config.opt_level(release);
+    if opt_level != OptLevel::Speed(0) {
+        let pass_manager_builder = PassManagerBuilder::create();
+        pass_manager_builder.set_optimization_level(llvm_opt_level(opt_level));
+        pass_manager_builder.set_size_level(match opt_level {
+            OptLevel::Speed(_) => 0,
+            OptLevel::Size => 1,
+            OptLevel::MinSize => 2,
+        });
+        pass_manager_builder.populate_function_pass_manager(&fpm);
+        pass_manager_builder.populate_module_pass_manager(&mpm);
+    }
 
-            log::info!("Lexing/Parsing");
+    fpm.initialize();
+
+    Target::initialize_all(&InitializationConfig::default());
+
+    let opt = llvm_opt_level(opt_level);
+    let reloc = RelocMode::PIC;
+    let model = CodeModel::Default;
+
+    let host_triple = TargetMachine::get_default_triple();
+    let target_triple = &match target {
+        Some(x) => TargetTriple::create(x),
+        None => TargetMachine::get_default_triple(),
+    };
+    let target_str = target_triple.as_str().to_str().unwrap();
+    let host_str = host_triple.as_str().to_str().unwrap();
+
+    let llvm_target = match Target::from_triple(target_triple) {
+        Ok(x) => x,
+        Err(x) => fail(
+            format,
+            format!("Unsupported target {}: {}", target_str.bold(), x),
+        ),
+    };
+
+    // Native builds can use everything the host has, cross builds only what every CPU has
+    let (default_cpu, default_features) = match target {
+        Some(_) => ("generic".to_string(), String::new()),
+        None => (
+            TargetMachine::get_host_cpu_name().to_string(),
+            TargetMachine::get_host_cpu_features().to_string(),
+        ),
+    };
+    let cpu = options
+        .cpu
+        .clone()
+        .or(project.config.cpu.clone())
+        .unwrap_or(default_cpu);
+    let features = options
+        .features
+        .clone()
+        .or(project.config.features.clone())
+        .unwrap_or(default_features);
+
+    let target_machine = match llvm_target.create_target_machine(
+        target_triple,
+        &cpu,
+        &features,
+        opt,
+        reloc,
+        model,
+    ) {
+        Some(x) => x,
+        None => fail(
+            format,
+            format!(
+                "Can't build for {} on CPU {}",
+                target_str.bold(),
+                cpu.bold()
+            ),
+        ),
+    };
+
+    // Set before compiling, debug info needs the pointer size
+    module.set_triple(target_triple);
+    module.set_data_layout(&target_machine.get_target_data().get_data_layout());
+
+    let mut compiler = Compiler::new(&context, module, builder, fpm);
+    compiler.nostd = nostd;
+    compiler.release = release;
+    // Errors that still let the files be parsed are reported together with the compile errors
+    compiler.diagnostics = diagnostics;
+    compiler.std_functions = manifest.functions.iter().map(|x| x.name.clone()).collect();
+    if !release {
+        let main = &files.last().unwrap().path;
+        let is_optimized = opt_level != OptLevel::Speed(0);
+        compiler.debug_info = Some(DebugInfo::new(&compiler.module, main, is_optimized));
+    }
+    let compiler = &compiler;
 
-            let tree = parse_file(&main_file);
+    // Add libstd functions
+    if !nostd {
+        if let Err(x) = compiler.declare_stdlib(&manifest) {
+            fail(format, format!("Invalid stdlib manifest: {}", x));
+        }
+    }
 
-            let context = Context::create();
-            let module = context.create_module("main");
-            let builder = context.create_builder();
+    log::info!("Converting AST to LLVM");
 
-            let fpm = PassManager::create(&module);
+    llvm_main(compiler, &files);
 
-            // TODO: Add more passes for better optimization
-            fpm.add_instruction_combining_pass();
-            fpm.add_reassociate_pass();
-            fpm.add_gvn_pass();
-            fpm.add_cfg_simplification_pass();
-            fpm.add_basic_alias_analysis_pass();
-            fpm.add_promote_memory_to_register_pass();
-            fpm.add_instruction_combining_pass();
-            fpm.add_reassociate_pass();
+    // Written before anything can stop the build, it is needed most when the module is invalid
+    if options.emit.contains(&Emit::LlvmIr) {
+        let path = build_dir.join(format!("{}.unopt.ll", project.config.name));
+        let result = compiler.module.print_to_file(&path);
+        written_to(&path, Emit::LlvmIr, result.map_err(|x| x.to_string()));
+    }
 
-            fpm.initialize();
+    if !compiler.diagnostics.is_empty() {
+        compiler.diagnostics.report(format);
+        std::process::exit(1);
+    }
 
-            let compiler = &Compiler {
-                context: &context,
-                module,
-                builder,
-                fpm,
-            };
+    if let Err(x) = compiler.module.verify() {
+        fail(format, format!("Module verification failed: {}", x));
+    }
 
-            // Add libstd functions
+    log::info!("Compiling");
 
-            let println_type = compiler.context.void_type().fn_type(
-                &[compiler
-                    .context
-                    .i8_type()
-                    .ptr_type(AddressSpace::default())
-                    .into()],
-                false,
-            );
-            compiler
-                .module
-                .add_function("coitusinterruptus", println_type, None);
+    let object_path = &emit_path(Emit::Obj);
 
-            let main_type = compiler.context.i32_type().fn_type(&[], false);
-            let main_fn = compiler.module.add_function("main", main_type, None);
+    log::info!("Optimizing with -O{}", opt_level.flag());
 
-            let entry_basic_block = compiler.context.append_basic_block(main_fn, "entry");
-            compiler.builder.position_at_end(entry_basic_block);
+    for function in compiler.module.get_functions() {
+        compiler.fpm.run_on(&function);
+    }
+    compiler.fpm.finalize();
+    mpm.run_on(&compiler.module);
 
-            log::info!("Converting AST to LLVM");
+    if options.emit.contains(&Emit::LlvmIr) {
+        let result = compiler.module.print_to_file(emit_path(Emit::LlvmIr));
+        written(Emit::LlvmIr, result.map_err(|x| x.to_string()));
+    }
 
-            llvm(&compiler, &tree);
+    if options.emit.contains(&Emit::Bc) {
+        let result = if compiler.module.write_bitcode_to_path(&emit_path(Emit::Bc)) {
+            Ok(())
+        } else {
+            Err("LLVM could not write the bitcode".to_string())
+        };
+        written(Emit::Bc, result);
+    }
 
-            compiler
-                .builder
-                .build_return(Some(&compiler.context.i32_type().const_zero()));
+    if options.emit.contains(&Emit::Asm) {
+        let result = target_machine.write_to_file(
+            &compiler.module,
+            inkwell::targets::FileType::Assembly,
+            &emit_path(Emit::Asm),
+        );
+        written(Emit::Asm, result.map_err(|x| x.to_string()));
+    }
 
-            match compiler.module.verify() {
-                Err(x) => {
-                    log::error!("Module verification failed: {}", x.to_string());
-                    std::process::exit(1);
-                }
-                _ => {}
-            };
+    let result = target_machine.write_to_file(
+        &compiler.module,
+        inkwell::targets::FileType::Object,
+        object_path,
+    );
+    written(Emit::Obj, result.map_err(|x| x.to_string()));
+
+    log::info!("Linking");
+
+    let mut build = cc::Build::new();
+    build
+        .target(target_str)
+        .out_dir(&build_dir)
+        .opt_level_str(&opt_level.flag())
+        .host(host_str)
+        .cargo_metadata(false);
+    if let Some(x) = options.linker.as_ref().or(project.config.linker.as_ref()) {
+        build.compiler(x);
+    }
+    let compiler = build.get_compiler();
 
-            log::info!("Compiling");
+    let output_file = build_dir.join(&project.config.name);
+    let output_file = output_file.to_str().unwrap();
 
-            Target::initialize_x86(&InitializationConfig::default());
+    let mut command = compiler.to_command();
+    command.arg(&object_path);
+    if let Some(x) = std_path {
+        command.arg(x);
+    }
+    command
+        .args(&project.config.link_args)
+        .args(["-o", output_file]);
 
-            let opt = OptimizationLevel::Aggressive;
-            let reloc = RelocMode::PIC;
-            let model = CodeModel::Default;
+    if let Err(x) = linker::link(command) {
+        fail(format, x);
+    }
 
-            let object_path = &build_dir.join(format!("{}.redd.it.o", project.config.name));
+    log::info!("Done! Executable is avalible at {}", output_file.bold());
 
-            let target = Target::from_name("x86-64").unwrap();
-            let target_triple = &TargetMachine::get_default_triple();
-            let target_machine = target
-                .create_target_machine(target_triple, "x86-64", "+avx2", opt, reloc, model)
-                .unwrap();
+    PathBuf::from(output_file)
+}
 
-            target_machine
-                .write_to_file(
-                    &compiler.module,
-                    inkwell::targets::FileType::Object,
-                    &object_path,
-                )
-                .unwrap();
+/// The exit code a shell would report for `status`, `128 + signal` if the program was killed
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
 
-            log::info!("Linking");
+fn main() {
+    let args = Args::parse();
+    logger::init().unwrap();
 
-            let target_str = target_triple.as_str().to_str().unwrap();
+    match args.command {
+        Commands::Cook { options } => {
+            cook(&options);
+        }
+        Commands::Brwww { options, args } => {
+            let executable = cook(&options);
 
-            let compiler = cc::Build::new()
-                .target(&target_str)
-                .out_dir(&build_dir)
-                .opt_level(if release { 3 } else { 0 })
-                .host(&target_str)
-                .cargo_metadata(false)
-                .get_compiler();
+            log::info!("Running {}", executable.display().to_string().bold());
 
-            let output_file = build_dir.join(&project.config.name);
-            let output_file = output_file.to_str().unwrap();
+            let status = match Command::new(&executable).args(args).status() {
+                Ok(x) => x,
+                Err(x) => {
+                    log::error!("Error running {}: {}", executable.display(), x);
+                    std::process::exit(1);
+                }
+            };
+            std::process::exit(exit_code(status));
+        }
+        Commands::Stdlib {
+            command: StdlibCommands::Update,
+        } => match stdlib::update() {
+            Ok(x) => log::info!(
+                "Done! libstd is available at {}",
+                x.display().to_string().bold()
+            ),
+            Err(x) => {
+                log::error!("Error updating libstd: {}", x);
+                std::process::exit(1);
+            }
+        },
+        Commands::Lsp => {
+            if let Err(x) = lsp::run() {
+                log::error!("Language server failed: {}", x);
+                std::process::exit(1);
+            }
+        }
+        Commands::New { name, git } => {
+            let path = env::current_dir().unwrap().join(&name);
+            let project = match Project::create(&path, &name) {
+                Ok(x) => x,
+                Err(x) => {
+                    log::error!("Error creating project: {}", x);
+                    std::process::exit(1);
+                }
+            };
 
-            compiler
-                .to_command()
-                .arg(&object_path)
-                .arg(std_path) // Could add nostd option that removes this
-                .args(["-o", output_file])
-                .spawn()
-                .unwrap();
+            if git {
+                if let Err(x) = Repository::init(&project.path) {
+                    log::error!("Error initializing git repository: {}", x);
+                    std::process::exit(1);
+                }
+            }
 
-            log::info!("Done! Executable is avalible at {}", output_file.bold());
+            log::info!(
+                "Created {} in {}",
+                project.config.name.bold(),
+                project.path.display().to_string().bold()
+            );
         }
-        Commands::New { name } => todo!(),
     }
 }
 
-fn parse_file(file: &str) -> Tree {
-    match RLParser::parse(Rule::Program, file) {
-        Ok(x) => parse(x),
-        Err(x) => error(x),
+/// Parses a file, errors are added to `diagnostics`. Only a syntax error or a bug in the parser
+/// leaves no tree.
+fn parse_file(path: &Path, source: &str, diagnostics: &Diagnostics) -> Result<Tree, Reported> {
+    match RLParser::parse(Rule::Program, source) {
+        Ok(x) => {
+            let context = ParseContext::new(path, diagnostics);
+            check_breaks(x.clone(), false, &context);
+            check_modules(x.clone(), &context);
+            parse(x, &context).map_err(|x| diagnostics.push(x.into()))
+        }
+        Err(x) => Err(diagnostics.push(Diagnostic::from_pest(x, path))),
     }
 }
@@ -1,12 +1,58 @@
-use crate::{from_pair::Parse, Rule};
+use crate::{
+    errors::{Code, Diagnostic},
+    from_pair::{span_of, Parse, ParseContext, ParseError},
+    Rule,
+};
+use std::{fmt, path::Path, rc::Rc};
 
 type Number = i128; // Number type
 
+/// Where a node comes from, lines and columns start at 1
+#[derive(Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Rc<Path>,
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset after the last character
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.col)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}..{})", self, self.start, self.end)
+    }
+}
+
 #[derive(Debug)]
 pub enum Term {
-    Number(Number),
-    String(String),
+    Number(Number, Span),
+    String(String, Span),
+    Boolean(Foolean, Span),
     Ident(Ident),
+    Call(Call),
+    /// An expression in parentheses
+    Expr(Box<Expr>),
+}
+
+/// `Yup` and `Nope`, and the values that only a Foolean can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Foolean {
+    Yup,
+    Nope,
+    /// `wat`
+    Dunno,
+    /// IO failure
+    Huh,
+    /// `Yup` or `Nope`, decided when it is evaluated
+    Yeet,
 }
 
 #[derive(Debug)]
@@ -26,10 +72,13 @@ pub struct Declaration {
 #[derive(Debug)]
 pub struct Loop {
     pub body: Tree,
+    pub span: Span,
 }
 
 #[derive(Debug)]
-pub struct Break;
+pub struct Break {
+    pub span: Span,
+}
 
 #[derive(Debug)]
 pub struct Function {
@@ -37,6 +86,7 @@ pub struct Function {
     pub declaration: Declaration,
     pub args: Vec<Declaration>,
     pub body: Tree,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -47,29 +97,36 @@ pub enum FunctionMod {
 
 #[derive(Debug)]
 pub struct Call {
+    /// The object a method is called on, `lab` in `call lab.method()`
+    pub receiver: Option<Ident>,
     pub ident: Ident,
-    pub args: Vec<Term>,
+    pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Throw {
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Import {
     pub path: Term,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Module {
     pub ident: Ident,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct TryCatch {
     pub r#try: Try,
     pub catch: Catch,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -82,6 +139,7 @@ pub struct Variable {
     pub modifiers: Vec<VariableMod>,
     pub declaration: Declaration,
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -93,11 +151,13 @@ pub enum VariableMod {
 pub struct Assignment {
     pub ident: Ident,
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct IfBlock {
     pub if_nodes: Vec<IfNode>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -128,11 +188,13 @@ pub struct Else {
 pub struct Class {
     pub ident: Ident,
     pub body: Tree,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Return {
     pub value: Expr,
+    pub span: Span,
 }
 
 // Operators
@@ -163,6 +225,7 @@ pub type BinaryExprTerm = ChainedExprTerm<MathOperator>;
 pub struct IndexExpr {
     pub term: Term,
     pub index: Index,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -174,6 +237,7 @@ pub enum Index {
 #[derive(Debug)]
 pub struct ChainedExpr<T> {
     pub terms: Vec<ChainedExprTerm<T>>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -184,7 +248,7 @@ pub struct ChainedExprTerm<T> {
 }
 
 #[derive(Debug)]
-pub struct Ident(pub String);
+pub struct Ident(pub String, pub Span);
 
 #[derive(Debug)]
 pub enum Expr {
@@ -192,7 +256,7 @@ pub enum Expr {
     ConditionalExpr(ConditionalExpr),
     IndexExpr(IndexExpr),
     Term(Term),
-    Null,
+    Null(Span),
 }
 
 // AST
@@ -216,57 +280,164 @@ pub enum Node {
 
 pub type Tree = Vec<Node>;
 
-pub fn parse_one(pair: pest::iterators::Pair<'_, Rule>) -> Option<Node> {
+impl Term {
+    pub fn span(&self) -> &Span {
+        match self {
+            Term::Number(_, x) | Term::String(_, x) | Term::Boolean(_, x) => x,
+            Term::Ident(x) => &x.1,
+            Term::Call(x) => &x.span,
+            Term::Expr(x) => x.span(),
+        }
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::BinaryExpr(x) => &x.span,
+            Expr::ConditionalExpr(x) => &x.span,
+            Expr::IndexExpr(x) => &x.span,
+            Expr::Term(x) => x.span(),
+            Expr::Null(x) => x,
+        }
+    }
+}
+
+impl Node {
+    pub fn span(&self) -> &Span {
+        match self {
+            Node::Loop(x) => &x.span,
+            Node::Break(x) => &x.span,
+            Node::Function(x) => &x.span,
+            Node::Call(x) => &x.span,
+            Node::Throw(x) => &x.span,
+            Node::Import(x) => &x.span,
+            Node::Module(x) => &x.span,
+            Node::TryCatch(x) => &x.span,
+            Node::Variable(x) => &x.span,
+            Node::Assignment(x) => &x.span,
+            Node::If(x) => &x.span,
+            Node::Class(x) => &x.span,
+            Node::Return(x) => &x.span,
+            Node::Expr(x) => x.span(),
+        }
+    }
+}
+
+pub fn parse_one(
+    pair: pest::iterators::Pair<'_, Rule>,
+    context: &ParseContext,
+) -> Result<Option<Node>, ParseError> {
+    let span = span_of(&pair, context);
     match pair.as_rule() {
         Rule::Statement => {
-            let statement = pair.into_inner().next().unwrap();
-            match statement.as_rule() {
-                Rule::Loop => Some(Node::Loop(Loop::parse_from(statement).unwrap())),
-                Rule::Function => Some(Node::Function(Function::parse_from(statement).unwrap())),
-                Rule::Call => Some(Node::Call(Call::parse_from(statement).unwrap())),
-                Rule::Break => Some(Node::Break(Break::parse_from(statement).unwrap())),
-                Rule::Throw => Some(Node::Throw(Throw::parse_from(statement).unwrap())),
-                Rule::Import => Some(Node::Import(Import::parse_from(statement).unwrap())),
-                Rule::Module => Some(Node::Module(Module::parse_from(statement).unwrap())),
-                Rule::TryCatch => Some(Node::TryCatch(TryCatch::parse_from(statement).unwrap())),
-                Rule::Variable => Some(Node::Variable(Variable::parse_from(statement).unwrap())),
+            let statement = pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| ParseError::new(&span, "MISSING_PAIR"))?;
+            Ok(Some(match statement.as_rule() {
+                Rule::Loop => Node::Loop(Loop::parse_from(statement, context)?),
+                Rule::Function => Node::Function(Function::parse_from(statement, context)?),
+                Rule::Call => Node::Call(Call::parse_from(statement, context)?),
+                Rule::Break => Node::Break(Break::parse_from(statement, context)?),
+                Rule::Throw => Node::Throw(Throw::parse_from(statement, context)?),
+                Rule::Import => Node::Import(Import::parse_from(statement, context)?),
+                Rule::Module => Node::Module(Module::parse_from(statement, context)?),
+                Rule::TryCatch => Node::TryCatch(TryCatch::parse_from(statement, context)?),
+                Rule::Variable => Node::Variable(Variable::parse_from(statement, context)?),
                 Rule::AssignmentStatement => {
-                    Some(Node::Assignment(Assignment::parse_from(statement).unwrap()))
+                    Node::Assignment(Assignment::parse_from(statement, context)?)
                 }
-                Rule::IfBlock => Some(Node::If(IfBlock::parse_from(statement).unwrap())),
-                Rule::Class => Some(Node::Class(Class::parse_from(statement).unwrap())),
-                Rule::Return => Some(Node::Return(Return::parse_from(statement).unwrap())),
-                _ => None,
-            }
+                Rule::IfBlock => Node::If(IfBlock::parse_from(statement, context)?),
+                Rule::Class => Node::Class(Class::parse_from(statement, context)?),
+                Rule::Return => Node::Return(Return::parse_from(statement, context)?),
+                x => {
+                    return Err(ParseError::new(
+                        &span_of(&statement, context),
+                        format!("NOT_A_STATEMENT({:?})", x),
+                    ))
+                }
+            }))
         }
         Rule::Expr => {
-            let expression = pair.into_inner().next().unwrap();
-            match expression.as_rule() {
-                Rule::BinaryExpr => Some(Node::Expr(Expr::BinaryExpr(
-                    BinaryExpr::parse_from(expression).unwrap(),
-                ))),
-                Rule::ConditionalExpr => Some(Node::Expr(Expr::ConditionalExpr(
-                    ConditionalExpr::parse_from(expression).unwrap(),
-                ))),
-                Rule::IndexExpr => Some(Node::Expr(Expr::IndexExpr(
-                    IndexExpr::parse_from(expression).unwrap(),
-                ))),
-                Rule::Null => Some(Node::Expr(Expr::Null)),
-                _ => Term::parse_from(expression).map(|x| Node::Expr(Expr::Term(x))),
-            }
+            let expression = pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| ParseError::new(&span, "MISSING_PAIR"))?;
+            Ok(Some(Node::Expr(match expression.as_rule() {
+                Rule::BinaryExpr => Expr::BinaryExpr(BinaryExpr::parse_from(expression, context)?),
+                Rule::ConditionalExpr => {
+                    Expr::ConditionalExpr(ConditionalExpr::parse_from(expression, context)?)
+                }
+                Rule::IndexExpr => Expr::IndexExpr(IndexExpr::parse_from(expression, context)?),
+                Rule::Null => Expr::Null(span_of(&expression, context)),
+                _ => Expr::Term(Term::parse_from(expression, context)?),
+            })))
         }
-        _ => None,
+        _ => Ok(None),
     }
 }
 
-pub fn parse(pairs: pest::iterators::Pairs<'_, Rule>) -> Tree {
+/// Builds the tree of a program or block. Stops at the first pair that doesn't match the grammar.
+pub fn parse(
+    pairs: pest::iterators::Pairs<'_, Rule>,
+    context: &ParseContext,
+) -> Result<Tree, ParseError> {
     let mut tree: Tree = vec![];
 
     for pair in pairs {
-        let node = parse_one(pair);
-        if node.is_some() {
-            tree.push(node.unwrap());
+        if let Some(node) = parse_one(pair, context)? {
+            tree.push(node);
+        }
+    }
+    Ok(tree)
+}
+
+/// Reports any `sthu` that is not inside of a `repeatdatshid`. Functions and classes start a new
+/// context, a loop around their definition does not count.
+pub fn check_breaks(
+    pairs: pest::iterators::Pairs<'_, Rule>,
+    in_loop: bool,
+    context: &ParseContext,
+) {
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::Break if !in_loop => {
+                context.diagnostics.push(
+                    Diagnostic::new(
+                        Code::BreakOutsideLoop,
+                        &span_of(&pair, context),
+                        "`sthu` outside of a loop",
+                    )
+                    .with_note("`sthu` can only leave a `repeatdatshid`"),
+                );
+            }
+            Rule::Loop => check_breaks(pair.into_inner(), true, context),
+            Rule::Function | Rule::Class => check_breaks(pair.into_inner(), false, context),
+            _ => check_breaks(pair.into_inner(), in_loop, context),
+        }
+    }
+}
+
+/// Reports any `subreddit` that is not the first statement of the file
+pub fn check_modules(pairs: pest::iterators::Pairs<'_, Rule>, context: &ParseContext) {
+    let first = pairs
+        .clone()
+        .next()
+        .filter(|x| x.as_rule() == Rule::Statement)
+        .and_then(|x| x.into_inner().next())
+        .map(|x| x.as_span());
+
+    for pair in pairs.flatten() {
+        if pair.as_rule() == Rule::Module && Some(pair.as_span()) != first {
+            context.diagnostics.push(
+                Diagnostic::new(
+                    Code::MisplacedModule,
+                    &span_of(&pair, context),
+                    "`subreddit` can only be used once, at the top of the file",
+                )
+                .with_help("move it to the first line"),
+            );
         }
     }
-    tree
 }
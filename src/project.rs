@@ -1,16 +1,135 @@
-use std::{fs, path::Path};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
+const HELLO_WORLD: &str = include_str!("../examples/hello_world.rl");
+
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct ProjectConfiguration {
     pub name: String,
     pub version: String,
+    /// Used instead of the default C compiler to link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker: Option<String>,
+    /// Extra arguments for the linker
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdlib: Option<StdlibConfiguration>,
+    /// Builds without the standard library
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nostd: bool,
+    /// Target triple to build for, the host if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<String>,
+    /// CPU features to enable or disable, such as `+avx2,-sse4.1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profiles>,
+}
+
+impl ProjectConfiguration {
+    /// The optimization level of a profile, `0` for debug and `3` for release by default
+    pub fn opt_level(&self, release: bool) -> OptLevel {
+        let profile = self
+            .profile
+            .as_ref()
+            .and_then(|x| if release { &x.release } else { &x.debug }.as_ref());
+
+        match profile.and_then(|x| x.opt_level) {
+            Some(x) => x,
+            None if release => OptLevel::Speed(3),
+            None => OptLevel::Speed(0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Profiles {
+    pub debug: Option<Profile>,
+    pub release: Option<Profile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    pub opt_level: Option<OptLevel>,
+}
+
+/// How much to optimize, written like `-O` of C compilers: 0 to 3, `s` or `z`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RawOptLevel", into = "RawOptLevel")]
+pub enum OptLevel {
+    /// 0 to 3
+    Speed(u32),
+    /// Optimize for size
+    Size,
+    /// Optimize for size, even at the cost of speed
+    MinSize,
+}
+
+impl OptLevel {
+    /// The value of `-O`
+    pub fn flag(self) -> String {
+        match self {
+            OptLevel::Speed(x) => x.to_string(),
+            OptLevel::Size => "s".to_string(),
+            OptLevel::MinSize => "z".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawOptLevel {
+    Number(u32),
+    Name(String),
+}
+
+impl TryFrom<RawOptLevel> for OptLevel {
+    type Error = String;
+
+    fn try_from(value: RawOptLevel) -> Result<Self, Self::Error> {
+        match value {
+            RawOptLevel::Number(x @ 0..=3) => Ok(OptLevel::Speed(x)),
+            RawOptLevel::Name(x) if x == "s" => Ok(OptLevel::Size),
+            RawOptLevel::Name(x) if x == "z" => Ok(OptLevel::MinSize),
+            RawOptLevel::Number(x) => {
+                Err(format!("invalid opt-level {}, expected 0 to 3, s or z", x))
+            }
+            RawOptLevel::Name(x) => {
+                Err(format!("invalid opt-level {}, expected 0 to 3, s or z", x))
+            }
+        }
+    }
+}
+
+impl From<OptLevel> for RawOptLevel {
+    fn from(value: OptLevel) -> Self {
+        match value {
+            OptLevel::Speed(x) => RawOptLevel::Number(x),
+            x => RawOptLevel::Name(x.flag()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StdlibConfiguration {
+    /// A checkout of the standard library or a prebuilt libstd.a, relative to the project
+    pub path: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Project {
-    pub path: String,
+    pub path: PathBuf,
     pub config: ProjectConfiguration,
 }
 
@@ -22,8 +141,130 @@ impl Project {
         let config: ProjectConfiguration = serde_yaml::from_str(&config).ok()?;
 
         Some(Project {
-            path: path.to_str().unwrap().to_string(),
+            path: path.to_path_buf(),
             config,
         })
     }
+
+    /// Creates a project with a hello world program at the specified path. The directory may
+    /// already exist, but only if it is empty.
+    pub fn create(path: &Path, name: &str) -> Result<Self, Box<dyn Error>> {
+        if path.exists() && fs::read_dir(path)?.next().is_some() {
+            return Err(format!("{} already exists and is not empty", path.display()).into());
+        }
+
+        let config = ProjectConfiguration {
+            name: name.to_string(),
+            version: "0.1.0".to_string(),
+            linker: None,
+            link_args: vec![],
+            stdlib: None,
+            nostd: false,
+            target: None,
+            cpu: None,
+            features: None,
+            profile: None,
+        };
+
+        fs::create_dir_all(path.join("src"))?;
+        fs::write(path.join("walter.yml"), serde_yaml::to_string(&config)?)?;
+        fs::write(path.join("src/main.rl"), HELLO_WORLD)?;
+        fs::write(path.join(".gitignore"), "build/\n")?;
+
+        Ok(Project {
+            path: path.to_path_buf(),
+            config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::{OptLevel, Project, ProjectConfiguration};
+
+    /// A path in the temp dir that nothing is at yet
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("walter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn create_refuses_a_directory_with_files() {
+        let path = temp_path("not-empty");
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("notes.txt"), "mine\n").unwrap();
+
+        let error = Project::create(&path, "app").unwrap_err().to_string();
+        let untouched = !path.join("walter.yml").exists() && !path.join("src").exists();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(
+            error.ends_with("already exists and is not empty"),
+            "{}",
+            error
+        );
+        assert!(untouched);
+    }
+
+    #[test]
+    fn create_uses_an_empty_directory() {
+        let path = temp_path("empty");
+        fs::create_dir_all(&path).unwrap();
+
+        let project = Project::create(&path, "app").unwrap();
+        let loaded = Project::from_path(&path).map(|x| x.config.name);
+        let main = path.join("src/main.rl").exists();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(project.config.name, "app");
+        assert_eq!(loaded.as_deref(), Some("app"));
+        assert!(main);
+    }
+
+    #[test]
+    fn opt_levels_of_profiles() {
+        let config: ProjectConfiguration = serde_yaml::from_str(
+            "name: app\nversion: 0.1.0\nprofile:\n  debug:\n    opt-level: 1\n  release:\n    opt-level: z\n",
+        )
+        .unwrap();
+        assert_eq!(config.opt_level(false), OptLevel::Speed(1));
+        assert_eq!(config.opt_level(true), OptLevel::MinSize);
+
+        let config: ProjectConfiguration =
+            serde_yaml::from_str("name: app\nversion: 0.1.0\n").unwrap();
+        assert_eq!(config.opt_level(false), OptLevel::Speed(0));
+        assert_eq!(config.opt_level(true), OptLevel::Speed(3));
+    }
+
+    #[test]
+    fn opt_levels_round_trip() {
+        let levels = [
+            OptLevel::Speed(0),
+            OptLevel::Speed(1),
+            OptLevel::Speed(2),
+            OptLevel::Speed(3),
+            OptLevel::Size,
+            OptLevel::MinSize,
+        ];
+
+        for level in levels {
+            let yaml = serde_yaml::to_string(&level).unwrap();
+            assert_eq!(yaml.trim(), level.flag());
+            assert_eq!(serde_yaml::from_str::<OptLevel>(&yaml).unwrap(), level);
+        }
+    }
+
+    #[test]
+    fn invalid_opt_levels() {
+        for (yaml, message) in [
+            ("4", "invalid opt-level 4, expected 0 to 3, s or z"),
+            ("x", "invalid opt-level x, expected 0 to 3, s or z"),
+        ] {
+            let error = serde_yaml::from_str::<OptLevel>(yaml).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
 }
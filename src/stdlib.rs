@@ -0,0 +1,320 @@
+//! Finds or builds `libstd.a`. The standard library is looked up, in order, at the `--stdlib`
+//! flag, the `stdlib` section of walter.yml and the checkout cached in `~/.walter/stdlib`. Each of
+//! these can be a checkout of the stdlib repository or a prebuilt archive. The network is only
+//! used by `walter stdlib update`, which downloads the cache.
+//!
+//! The functions of the stdlib are declared from `std.yml`, which sits in the root of the checkout
+//! or next to a prebuilt archive.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+
+use crate::{git::clone_else_pull, project::Project};
+
+const STDLIB_URL: &str = "https://github.com/elijah629/redditlang-std";
+const MANIFEST: &str = "std.yml";
+/// Used for stdlib checkouts that don't have a manifest yet, and nostd builds without a stdlib
+const DEFAULT_MANIFEST: &str = include_str!("../std.yml");
+
+#[derive(Deserialize, Debug)]
+pub struct Manifest {
+    pub functions: Vec<FunctionDeclaration>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    /// RedditLang type names
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub returns: Option<String>,
+    /// Takes any number of arguments after `args`
+    #[serde(default)]
+    pub variadic: bool,
+}
+
+impl Manifest {
+    /// The declarations walter was built with
+    pub fn builtin() -> Self {
+        serde_yaml::from_str(DEFAULT_MANIFEST).unwrap()
+    }
+
+    /// Reads the manifest of the stdlib at `path`, a checkout or an archive
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let dir = if path.is_file() {
+            path.parent().unwrap()
+        } else {
+            path
+        };
+
+        let manifest = match fs::read_to_string(dir.join(MANIFEST)) {
+            Ok(x) => x,
+            Err(_) => {
+                log::warn!(
+                    "{} has no {}, using the declarations walter was built with",
+                    dir.display(),
+                    MANIFEST
+                );
+                DEFAULT_MANIFEST.to_string()
+            }
+        };
+        Ok(serde_yaml::from_str(&manifest)?)
+    }
+}
+
+/// The checkout of the stdlib used when a project doesn't point at its own
+pub fn cache_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".walter").join("stdlib")
+}
+
+/// The stdlib selected by the `--stdlib` flag or walter.yml, otherwise the cache
+pub fn source(flag: Option<&Path>, project: &Project) -> PathBuf {
+    let configured = project
+        .config
+        .stdlib
+        .as_ref()
+        .and_then(|x| x.path.as_ref())
+        .map(|x| Path::new(&project.path).join(x));
+
+    flag.map(Path::to_path_buf)
+        .or(configured)
+        .unwrap_or_else(cache_dir)
+}
+
+/// Returns the path of `libstd.a` and its manifest, building it first if it is missing or out of
+/// date. The archive is built for `target`, or the host if there is none.
+pub fn locate(
+    flag: Option<&Path>,
+    project: &Project,
+    target: Option<&str>,
+) -> Result<(PathBuf, Manifest), Box<dyn Error>> {
+    let path = source(flag, project);
+    if path == cache_dir() && !path.exists() {
+        return Err("the standard library is not downloaded, run `walter stdlib update`".into());
+    }
+
+    if !path.exists() {
+        return Err(format!("{} does not exist", path.display()).into());
+    }
+    let manifest = Manifest::read(&path)?;
+
+    if path.is_file() {
+        return Ok((path, manifest));
+    }
+    Ok((build(&path, target)?, manifest))
+}
+
+/// Pulls the latest stdlib into the cache, downloads its dependencies and rebuilds it for the host
+pub fn update() -> Result<PathBuf, Box<dyn Error>> {
+    let cache = cache_dir();
+    fs::create_dir_all(cache.parent().unwrap())?;
+    clone_else_pull(STDLIB_URL, &cache, "main")?;
+
+    let output = Command::new("cargo")
+        .arg("fetch")
+        .current_dir(&cache)
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into());
+    }
+    build(&cache, None)
+}
+
+/// 64 bit FNV-1a. Unlike `DefaultHasher` it gives the same hash with every Rust version, so
+/// archives stay current across toolchain upgrades.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for x in bytes {
+            self.0 = (self.0 ^ *x as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// A hash of the files of a checkout, without build output and git metadata
+fn fingerprint(dir: &Path) -> io::Result<String> {
+    let mut hasher = Fnv::new();
+    hash_dir(dir, &mut hasher)?;
+    Ok(format!("{:016x}", hasher.0))
+}
+
+fn hash_dir(dir: &Path, hasher: &mut Fnv) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|x| x.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let is_output = matches!(
+            path.extension().and_then(|x| x.to_str()),
+            Some("a" | "hash")
+        );
+        if entry.file_name() == "target" || entry.file_name() == ".git" || is_output {
+            continue;
+        }
+
+        // Lengths go first, so moving bytes between names and contents changes the hash
+        let name = entry.file_name().to_string_lossy().into_owned();
+        hasher.write(&(name.len() as u64).to_le_bytes());
+        hasher.write(name.as_bytes());
+        if entry.file_type()?.is_dir() {
+            hasher.write(b"/");
+            hash_dir(&path, hasher)?;
+        } else {
+            let contents = fs::read(&path)?;
+            hasher.write(&(contents.len() as u64).to_le_bytes());
+            hasher.write(&contents);
+        }
+    }
+    Ok(())
+}
+
+/// Builds `libstd.a` in a checkout, unless it was already built from the same files. Archives for
+/// other targets are named `libstd-<target>.a`.
+fn build(dir: &Path, target: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+    let (archive, target_dir) = match target {
+        Some(x) => (
+            dir.join(format!("libstd-{}.a", x)),
+            dir.join("target").join(x),
+        ),
+        None => (dir.join("libstd.a"), dir.join("target")),
+    };
+    // Stores the fingerprint of the files the archive was built from, next to it
+    let hash_file = archive.with_extension("hash");
+    let is_current = fs::read_to_string(&hash_file).ok() == Some(fingerprint(dir)?);
+    if archive.exists() && is_current {
+        return Ok(archive);
+    }
+
+    log::info!("Building the standard library");
+
+    // Only `walter stdlib update` uses the network, it fetches the dependencies of the cache
+    let mut command = Command::new("cargo");
+    command
+        .arg("build")
+        .arg("--release")
+        .arg("--offline")
+        .current_dir(dir);
+    if let Some(x) = target {
+        command.args(["--target", x]);
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "{}\nhelp: dependencies are not downloaded while building, run `cargo fetch` in {}",
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+            dir.display()
+        )
+        .into());
+    }
+
+    fs::rename(target_dir.join("release/libstd.a"), &archive)?;
+
+    Command::new("cargo")
+        .arg("clean")
+        .current_dir(dir)
+        .output()?;
+
+    // Building can write Cargo.lock, so the files are hashed again
+    fs::write(&hash_file, fingerprint(dir)?)?;
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::{fingerprint, Manifest};
+
+    /// An empty directory of its own in the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("walter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fingerprint_only_depends_on_the_sources() {
+        let dir = temp_dir("fingerprint");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "pub fn x() {}\n").unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\n").unwrap();
+        // Hashes are stored, so they must not change with the Rust version
+        assert_eq!(fingerprint(&dir).unwrap(), "4d5930455edea137");
+
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("target/output"), "").unwrap();
+        fs::write(dir.join("libstd.a"), "").unwrap();
+        fs::write(dir.join("libstd.hash"), "").unwrap();
+        let unchanged = fingerprint(&dir).unwrap();
+
+        fs::write(dir.join("src/lib.rs"), "pub fn y() {}\n").unwrap();
+        let changed = fingerprint(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(unchanged, "4d5930455edea137");
+        assert_ne!(changed, unchanged);
+    }
+
+    /// The names of the functions a manifest declares
+    fn names(manifest: &Manifest) -> Vec<&str> {
+        manifest.functions.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[test]
+    fn manifest_of_the_stdlib() {
+        let dir = temp_dir("manifest");
+        fs::write(
+            dir.join("std.yml"),
+            "functions:\n  - name: shout\n    args: [String, Number]\n    returns: Number\n    variadic: true\n  - name: nap\n",
+        )
+        .unwrap();
+        fs::write(dir.join("libstd.a"), "").unwrap();
+        let checkout = Manifest::read(&dir).unwrap();
+        let archive = Manifest::read(&dir.join("libstd.a")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names(&checkout), ["shout", "nap"]);
+        assert_eq!(names(&archive), ["shout", "nap"]);
+        let shout = &checkout.functions[0];
+        assert_eq!(shout.args, ["String", "Number"]);
+        assert_eq!(shout.returns.as_deref(), Some("Number"));
+        assert!(shout.variadic);
+        let nap = &checkout.functions[1];
+        assert!(nap.args.is_empty());
+        assert_eq!(nap.returns, None);
+        assert!(!nap.variadic);
+    }
+
+    #[test]
+    fn missing_manifest_uses_the_builtin_one() {
+        let dir = temp_dir("no-manifest");
+        let manifest = Manifest::read(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let builtin = Manifest::builtin();
+        assert!(names(&builtin).contains(&"coitusinterruptus"));
+        assert_eq!(names(&manifest), names(&builtin));
+    }
+
+    #[test]
+    fn invalid_manifest_is_an_error() {
+        let dir = temp_dir("bad-manifest");
+        fs::write(dir.join("std.yml"), "functions:\n  - args: [String]\n").unwrap();
+        let manifest = Manifest::read(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(manifest.is_err());
+    }
+}
@@ -1,12 +1,22 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::hash::Hash;
 
-/// Checks if each item in an iterators is unique
-pub fn is_unique<T>(iter: T) -> bool
+/// Finds items that are equal to an earlier one. Returns the index of the first occurrence and of
+/// the repeat, for each repeat.
+pub fn duplicates<T>(iter: T) -> Vec<(usize, usize)>
 where
     T: IntoIterator,
     T::Item: Eq + Hash,
 {
-    let mut uniq = HashSet::new();
-    iter.into_iter().all(move |x| uniq.insert(x))
+    let mut first = HashMap::new();
+    iter.into_iter()
+        .enumerate()
+        .filter_map(|(i, x)| match first.get(&x) {
+            Some(&x) => Some((x, i)),
+            None => {
+                first.insert(x, i);
+                None
+            }
+        })
+        .collect()
 }